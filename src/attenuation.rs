@@ -0,0 +1,198 @@
+//! attenuation of tokens on behalf of clients that cannot run Biscuit
+use biscuit::builder::BlockBuilder;
+use biscuit::macros::check;
+use biscuit::macros::fact;
+use biscuit_auth as biscuit;
+use serde::Deserialize;
+use serde::Serialize;
+
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::BoxError;
+
+/// body of an attenuation request
+#[derive(Debug, Clone, Deserialize)]
+pub struct AttenuationRequest {
+    /// base64 encoded token
+    pub token: String,
+    /// root fields the attenuated token can query or mutate
+    pub operations: Vec<String>,
+    /// validity of the attenuated token, in seconds
+    pub ttl: u64,
+}
+
+/// body of an attenuation response
+#[derive(Debug, Clone, Serialize)]
+pub struct AttenuationResponse {
+    /// base64 encoded attenuated token
+    pub token: String,
+}
+
+/// appends a block restricting the token to these root fields, until `ttl` has elapsed
+///
+/// Mutations are restricted as well as queries, otherwise a token attenuated to a list of
+/// queries could still run any mutation.
+pub fn restrict(
+    token: &biscuit::Biscuit,
+    operations: &[String],
+    ttl: Duration,
+) -> Result<biscuit::Biscuit, BoxError> {
+    if operations.is_empty() {
+        return Err(BoxError::from("no operation allowed"));
+    }
+
+    // root field names are written directly in the Datalog code, so they must be valid
+    // GraphQL names
+    if let Some(operation) = operations.iter().find(|operation| !is_name(operation)) {
+        return Err(BoxError::from(format!(
+            "invalid operation name `{operation}`"
+        )));
+    }
+
+    let operations = operations
+        .iter()
+        .map(|operation| format!("\"{operation}\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut block = BlockBuilder::new();
+    block.add_code(format!(
+        r#"check all query($op), [{operations}].contains($op);
+        check all mutation($op), [{operations}].contains($op);"#
+    ))?;
+    // the ttl comes from the client
+    let expiration = SystemTime::now()
+        .checked_add(ttl)
+        .ok_or_else(|| BoxError::from("invalid ttl"))?;
+    block.add_check(check!(
+        "check if time($time), $time < {expiration}",
+        expiration = expiration
+    ))?;
+
+    Ok(token.append(block)?)
+}
+
+/// mints a token with the blocks of a sealed token, signed by the root key, so it can be
+/// attenuated again
+///
+/// This needs the root private key on the router: whoever controls the router can then mint
+/// any token. The token must have been verified with the root public key before.
+///
+/// The minted token has new revocation ids, so the ids of the sealed token's blocks are
+/// added to its authority block as `original_revocation_id(index, id)` facts, for services
+/// checking revocation lists. Tokens with third party blocks are rejected, since their
+/// facts would lose the external signature that the authorizers trust.
+pub fn unseal(
+    token: &biscuit::Biscuit,
+    root: &biscuit::KeyPair,
+) -> Result<biscuit::Biscuit, BoxError> {
+    if token.external_public_keys().iter().any(Option::is_some) {
+        return Err(BoxError::from(
+            "tokens with third party blocks cannot be unsealed",
+        ));
+    }
+
+    let mut builder = biscuit::Biscuit::builder();
+    builder.add_code(token.print_block_source(0)?)?;
+    for (index, id) in token.revocation_identifiers().into_iter().enumerate() {
+        builder.add_fact(fact!(
+            "original_revocation_id({index}, {id})",
+            index = index as i64,
+            id = id
+        ))?;
+    }
+    let mut unsealed = builder.build(root)?;
+
+    for index in 1..token.block_count() {
+        let mut block = BlockBuilder::new();
+        block.add_code(token.print_block_source(index)?)?;
+        unsealed = unsealed.append(block)?;
+    }
+
+    Ok(unsealed)
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map_or(false, |c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use biscuit::macros::biscuit;
+    use biscuit::macros::block;
+
+    use super::*;
+
+    #[test]
+    fn restricted_operations() {
+        let root = biscuit::KeyPair::new();
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+
+        let operations = vec!["me".to_string(), "__schema".to_string()];
+        let attenuated = restrict(&token, &operations, Duration::from_secs(60)).unwrap();
+        assert_eq!(attenuated.block_count(), 2);
+        let source = attenuated.print_block_source(1).unwrap();
+        assert!(source.contains("check all query($op)"));
+        assert!(source.contains("check all mutation($op)"));
+        assert!(source.contains("check if time($time)"));
+
+        assert!(restrict(&token, &[], Duration::from_secs(60)).is_err());
+        // an error, not a panic
+        assert!(restrict(&token, &operations, Duration::from_secs(u64::MAX)).is_err());
+        assert!(restrict(
+            &token,
+            &["me\"), true or query(\"".to_string()],
+            Duration::from_secs(60)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn unsealed_token() {
+        let root = biscuit::KeyPair::new();
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+        let token = token.append(block!(r#"check if query("me");"#)).unwrap();
+        let sealed = token.seal().unwrap();
+        assert!(sealed.append(BlockBuilder::new()).is_err());
+
+        let unsealed = unseal(&sealed, &root).unwrap();
+        assert_eq!(unsealed.block_count(), 2);
+        assert!(unsealed.print_block_source(0).unwrap().contains("user(1)"));
+        assert!(unsealed
+            .print_block_source(1)
+            .unwrap()
+            .contains("check if query(\"me\")"));
+        assert!(unsealed.append(BlockBuilder::new()).is_ok());
+
+        let ids = sealed.revocation_identifiers();
+        let source = unsealed.print_block_source(0).unwrap();
+        assert!(source.contains(&format!(
+            "original_revocation_id(0, hex:{})",
+            hex::encode(&ids[0])
+        )));
+        assert!(source.contains(&format!(
+            "original_revocation_id(1, hex:{})",
+            hex::encode(&ids[1])
+        )));
+    }
+
+    #[test]
+    fn third_party_blocks() {
+        let root = biscuit::KeyPair::new();
+        let external = biscuit::KeyPair::new();
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+        let block = token
+            .third_party_request()
+            .unwrap()
+            .create_block(&external.private(), block!("admin(true);"))
+            .unwrap();
+        let token = token.append_third_party(external.public(), block).unwrap();
+
+        assert!(unseal(&token.seal().unwrap(), &root).is_err());
+    }
+}
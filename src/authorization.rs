@@ -0,0 +1,1575 @@
+use apollo_compiler::values::OperationType;
+use apollo_compiler::values::Selection;
+use apollo_compiler::values::SelectionSet;
+use apollo_compiler::ApolloCompiler;
+use apollo_router::graphql;
+use biscuit::builder::Binary;
+use biscuit::builder::Check;
+use biscuit::builder::Fact;
+use biscuit::builder::Op;
+use biscuit::builder::Rule;
+use biscuit::builder::Term;
+use biscuit::error::FailedCheck;
+use biscuit::error::Logic;
+use biscuit::error::MatchedPolicy;
+use biscuit::error::Token;
+use biscuit::format::schema;
+use biscuit::macros::fact;
+use biscuit_auth as biscuit;
+use prost::Message;
+use sha2::Digest;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::config::Conf;
+use crate::config::LimitsConf;
+use crate::config::OperationKind;
+use crate::config::RequestFactsConf;
+use crate::config::SealedConf;
+use crate::request;
+use crate::BoxError;
+
+/// policies applied to requests: the root key verifying tokens, and the authorizer code
+#[derive(Debug, Clone)]
+pub struct Policies {
+    root: biscuit::PublicKey,
+    code: String,
+    /// facts derived from the HTTP request
+    request_facts: RequestFactsConf,
+    /// limits on the tokens and on the authorizer's execution
+    limits: LimitsConf,
+    /// named third party keys, that the authorizer code references as `trusting {name}`
+    trusted_keys: HashMap<String, biscuit::PublicKey>,
+    /// operations requiring sealed tokens
+    sealed: SealedConf,
+    /// source of the checks and policies of the authorizer code
+    checks: Vec<String>,
+    policies: Vec<String>,
+}
+
+/// options of [`Policies`], the authorizer code is parsed when they are built
+#[derive(Debug, Clone)]
+pub struct PoliciesBuilder {
+    root: biscuit::PublicKey,
+    code: String,
+    request_facts: RequestFactsConf,
+    limits: LimitsConf,
+    trusted_keys: HashMap<String, biscuit::PublicKey>,
+    sealed: SealedConf,
+}
+
+impl PoliciesBuilder {
+    /// the options of the plugin configuration
+    pub fn from_conf(conf: &Conf) -> Result<Self, BoxError> {
+        Ok(Policies::builder(conf.public_root()?, conf.load_code()?)
+            .trusted_keys(conf.trusted_keys()?)
+            .request_facts(conf.request_facts.clone())
+            .limits(conf.limits.clone())
+            .sealed(conf.sealed.clone()))
+    }
+
+    /// another root key, like a test key signing sample tokens
+    pub fn root(mut self, root: biscuit::PublicKey) -> Self {
+        self.root = root;
+        self
+    }
+
+    /// other authorizer code, like the shadow policies
+    pub fn code(mut self, code: String) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn trusted_keys(mut self, trusted_keys: HashMap<String, biscuit::PublicKey>) -> Self {
+        self.trusted_keys = trusted_keys;
+        self
+    }
+
+    pub fn request_facts(mut self, request_facts: RequestFactsConf) -> Self {
+        self.request_facts = request_facts;
+        self
+    }
+
+    pub fn limits(mut self, limits: LimitsConf) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn sealed(mut self, sealed: SealedConf) -> Self {
+        self.sealed = sealed;
+        self
+    }
+
+    pub fn build(self) -> Result<Policies, BoxError> {
+        let mut authorizer = biscuit::Authorizer::new();
+        authorizer.add_code_with_params(&self.code, HashMap::new(), self.trusted_keys.clone())?;
+        let (_, _, checks, policies) = authorizer.dump();
+
+        Ok(Policies {
+            root: self.root,
+            code: self.code,
+            request_facts: self.request_facts,
+            limits: self.limits,
+            trusted_keys: self.trusted_keys,
+            sealed: self.sealed,
+            checks: checks.iter().map(|check| check.to_string()).collect(),
+            policies: policies.iter().map(|policy| policy.to_string()).collect(),
+        })
+    }
+}
+
+/// result of the authorization path on a request
+pub struct Authorization {
+    pub authorizer: biscuit::Authorizer,
+    pub token: Option<biscuit::Biscuit>,
+    pub result: Result<usize, Token>,
+    /// the token does not match the `sealed` options, whatever the authorizer's result
+    pub sealed: Option<SealedToken>,
+}
+
+impl Authorization {
+    /// decision on the request, including the `sealed` options
+    pub fn decision(&self) -> Decision {
+        match self.sealed {
+            Some(e) => Decision::SealedToken(e),
+            None => Decision::new(&self.result),
+        }
+    }
+}
+
+impl Policies {
+    /// policies with the default options, that the builder can change
+    pub fn builder(root: biscuit::PublicKey, code: String) -> PoliciesBuilder {
+        PoliciesBuilder {
+            root,
+            code,
+            request_facts: RequestFactsConf::default(),
+            limits: LimitsConf::default(),
+            trusted_keys: HashMap::new(),
+            sealed: SealedConf::default(),
+        }
+    }
+
+    pub fn from_conf(conf: &Conf) -> Result<Self, BoxError> {
+        PoliciesBuilder::from_conf(conf)?.build()
+    }
+
+    pub fn root(&self) -> biscuit::PublicKey {
+        self.root
+    }
+
+    /// extracts the token from the request, and verifies its signature and its size
+    pub fn extract_token(
+        &self,
+        request: &http::Request<graphql::Request>,
+    ) -> Result<Option<biscuit::Biscuit>, BoxError> {
+        let token = match extract_token_string(request)? {
+            None => return Ok(None),
+            Some(token) => token,
+        };
+
+        // large tokens are rejected before spending time parsing them and verifying signatures
+        if let Some(max) = self.limits.max_token_size {
+            if token.len() > max {
+                return Err(LimitExceeded::TokenSize.into());
+            }
+        }
+
+        let token = biscuit::Biscuit::from_base64(token, &self.root)?;
+
+        if let Some(max) = self.limits.max_blocks {
+            if token.block_count() > max {
+                return Err(LimitExceeded::Blocks.into());
+            }
+        }
+
+        if self.limits.max_facts.is_some() || self.limits.max_rules.is_some() {
+            // counted from the serialized blocks, without printing and parsing their Datalog
+            let (mut facts, mut rules) = (0, 0);
+            let container = token.container();
+            for block in std::iter::once(&container.authority).chain(container.blocks.iter()) {
+                let block = schema::Block::decode(block.data.as_slice())?;
+                facts += block.facts_v2.len();
+                rules += block.rules_v2.len();
+            }
+
+            if self.limits.max_facts.map_or(false, |max| facts > max) {
+                return Err(LimitExceeded::Facts.into());
+            }
+            if self.limits.max_rules.map_or(false, |max| rules > max) {
+                return Err(LimitExceeded::Rules.into());
+            }
+        }
+
+        Ok(Some(token))
+    }
+
+    /// facts derived from the HTTP request, like the client name or IP address
+    pub fn request_facts(
+        &self,
+        request: &http::Request<graphql::Request>,
+    ) -> Result<Vec<biscuit::builder::Fact>, BoxError> {
+        request::facts(&self.request_facts, request)
+    }
+
+    /// runs the plugin's authorization path on a request
+    pub fn authorize(
+        &self,
+        request: &http::Request<graphql::Request>,
+    ) -> Result<Authorization, BoxError> {
+        /*** Get the token from the request
+         *
+         * If there's no Authorization header, we can still apply the authorizer policies on an unauthenticated request
+         * ***/
+        let token = self.extract_token(request)?;
+
+        self.authorize_token(request, token)
+    }
+
+    /// runs the plugin's authorization path on a request, with a token already extracted from it
+    pub fn authorize_token(
+        &self,
+        request: &http::Request<graphql::Request>,
+        token: Option<biscuit::Biscuit>,
+    ) -> Result<Authorization, BoxError> {
+        /*** Parse the query to observe the requested operation
+         *
+         * For persisted queries, the router has already replaced the hash with the query
+         * ***/
+        let body = request.body();
+        let query = body.query.as_deref().ok_or(RequestError::MissingQuery)?;
+        let operation = Operation::parse(query, body.operation_name.as_deref())?;
+        let sealed = self.check_sealed(&operation, token.as_ref()).err();
+
+        /*** Create the authorizer
+         *
+         * A fact will be added for each root operation, that can then be checked by the token
+         *  ***/
+        let mut authorizer = authorizer(&operation, &self.code, &self.trusted_keys)?;
+        authorizer.set_limits(self.limits.authorizer_limits());
+
+        // tokens can be restricted to an allowlist of persisted queries. The hash comes from
+        // the client, so it must be the hash of the query that will be executed
+        if let Some(hash) = persisted_query_hash(body) {
+            if !hash.eq_ignore_ascii_case(&operation.hash) {
+                return Err(RequestError::PersistedQueryMismatch.into());
+            }
+            authorizer.add_fact(fact!(
+                "persisted_query({hash})",
+                hash = operation.hash.as_str()
+            ))?;
+        }
+
+        for fact in self.request_facts(request)? {
+            authorizer.add_fact(fact)?;
+        }
+
+        if let Some(token) = token.as_ref() {
+            authorizer.add_token(token)?;
+        }
+
+        let result = authorizer.authorize();
+        Ok(Authorization {
+            authorizer,
+            token,
+            result,
+            sealed,
+        })
+    }
+
+    /// applies the `sealed` options to the operation
+    fn check_sealed(
+        &self,
+        operation: &Operation,
+        token: Option<&biscuit::Biscuit>,
+    ) -> Result<(), SealedToken> {
+        let required = self.sealed.require.contains(&operation.kind());
+        if !required && !self.sealed.reject {
+            return Ok(());
+        }
+
+        let sealed = token.map_or(false, is_sealed);
+        if sealed && self.sealed.reject {
+            Err(SealedToken::Rejected)
+        } else if !sealed && required {
+            Err(SealedToken::Required)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// describes the facts, the result of each check per block, and the policy that matched
+    pub fn explain(&self, authorization: &Authorization) -> Explanation {
+        let (facts, _, _, _) = authorization.authorizer.dump();
+        let mut facts: Vec<String> = facts.iter().map(|fact| fact.to_string()).collect();
+        facts.sort();
+
+        let failed_checks = match &authorization.result {
+            Err(Token::FailedLogic(Logic::Unauthorized { checks, .. }))
+            | Err(Token::FailedLogic(Logic::NoMatchingPolicy { checks })) => checks.as_slice(),
+            _ => &[],
+        };
+        let failed = |block_id: Option<u32>, check_id: u32| {
+            failed_checks.iter().any(|check| match check {
+                FailedCheck::Block(check) => {
+                    block_id == Some(check.block_id) && check.check_id == check_id
+                }
+                FailedCheck::Authorizer(check) => block_id.is_none() && check.check_id == check_id,
+            })
+        };
+
+        let mut checks = Vec::new();
+        for (check_id, rule) in self.checks.iter().enumerate() {
+            checks.push(CheckResult {
+                block_id: None,
+                rule: rule.clone(),
+                success: !failed(None, check_id as u32),
+            });
+        }
+
+        if let Some(token) = authorization.token.as_ref() {
+            for block_id in 0..token.block_count() {
+                // the block is parsed back to get its checks in order, with their ids
+                let mut block = biscuit::builder::BlockBuilder::new();
+                if let Ok(source) = token.print_block_source(block_id) {
+                    let _ = block.add_code(source);
+                }
+
+                for (check_id, check) in block.checks.iter().enumerate() {
+                    checks.push(CheckResult {
+                        block_id: Some(block_id as u32),
+                        rule: check.to_string(),
+                        success: !failed(Some(block_id as u32), check_id as u32),
+                    });
+                }
+            }
+        }
+
+        let matched_policy = match &authorization.result {
+            Ok(index) => Some(*index),
+            Err(Token::FailedLogic(Logic::Unauthorized { policy, .. })) => match policy {
+                MatchedPolicy::Allow(index) | MatchedPolicy::Deny(index) => Some(*index),
+            },
+            Err(_) => None,
+        };
+
+        Explanation {
+            facts,
+            checks,
+            policies: self.policies.clone(),
+            matched_policy,
+            decision: authorization.decision(),
+        }
+    }
+}
+
+/// readable description of an authorizer run
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub facts: Vec<String>,
+    pub checks: Vec<CheckResult>,
+    pub policies: Vec<String>,
+    pub matched_policy: Option<usize>,
+    pub decision: Decision,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    /// block of the token containing this check, `None` for the authorizer's checks
+    pub block_id: Option<u32>,
+    pub rule: String,
+    pub success: bool,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "facts:")?;
+        for fact in &self.facts {
+            writeln!(f, "    {fact}")?;
+        }
+
+        let mut current_block = None;
+        for (index, check) in self.checks.iter().enumerate() {
+            if index == 0 || check.block_id != current_block {
+                current_block = check.block_id;
+                match check.block_id {
+                    None => writeln!(f, "authorizer checks:")?,
+                    Some(0) => writeln!(f, "authority block checks:")?,
+                    Some(id) => writeln!(f, "block {id} checks:")?,
+                }
+            }
+            let status = if check.success { "ok" } else { "FAILED" };
+            writeln!(f, "    [{status}] {}", check.rule)?;
+        }
+
+        writeln!(f, "policies:")?;
+        for (index, policy) in self.policies.iter().enumerate() {
+            let marker = if Some(index) == self.matched_policy {
+                "=>"
+            } else {
+                "  "
+            };
+            writeln!(f, "  {marker} {index}: {policy}")?;
+        }
+
+        let result = match &self.decision {
+            Decision::Allow(_) => "allowed".to_string(),
+            Decision::Deny(index) => format!("denied by policy {index}"),
+            Decision::FailedChecks(checks) => format!("denied by {} failed checks", checks.len()),
+            Decision::NoMatchingPolicy => "denied: no matching policy".to_string(),
+            Decision::SealedToken(e) => format!("denied: {e}"),
+            Decision::Error(e) => format!("error: {e}"),
+        };
+        write!(f, "result: {result}")
+    }
+}
+
+/// error returned when a token exceeds the configured limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    TokenSize,
+    Blocks,
+    Facts,
+    Rules,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::TokenSize => write!(f, "token too large"),
+            LimitExceeded::Blocks => write!(f, "too many blocks in the token"),
+            LimitExceeded::Facts => write!(f, "too many facts in the token"),
+            LimitExceeded::Rules => write!(f, "too many rules in the token"),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// error returned when a token does not match the `sealed` options
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SealedToken {
+    /// the operation requires a sealed token
+    Required,
+    /// sealed tokens are rejected
+    Rejected,
+}
+
+impl fmt::Display for SealedToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SealedToken::Required => write!(f, "sealed token required"),
+            SealedToken::Rejected => write!(f, "sealed tokens are not accepted"),
+        }
+    }
+}
+
+impl std::error::Error for SealedToken {}
+
+/// error returned when the request itself is invalid, before any token is verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestError {
+    /// the `Authorization` header is not a valid string
+    InvalidHeader,
+    /// the `Authorization` header does not contain a bearer token
+    NotBearer,
+    MissingQuery,
+    /// the operation selected by `operationName` is not in the query
+    OperationNotFound,
+    /// the persisted query hash sent by the client is not the hash of the query
+    PersistedQueryMismatch,
+    /// the query is nested more than `MAX_NESTING` levels
+    TooNested,
+}
+
+impl RequestError {
+    /// true if the error is about the `Authorization` header, not the query
+    pub fn is_header_error(&self) -> bool {
+        matches!(self, RequestError::InvalidHeader | RequestError::NotBearer)
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::InvalidHeader => write!(f, "invalid authorization header"),
+            RequestError::NotBearer => write!(f, "not a bearer token"),
+            RequestError::MissingQuery => write!(f, "missing query"),
+            RequestError::OperationNotFound => write!(f, "cannot find operation"),
+            RequestError::PersistedQueryMismatch => {
+                write!(f, "persisted query hash does not match the query")
+            }
+            RequestError::TooNested => {
+                write!(f, "the query is nested more than {MAX_NESTING} levels")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// the operation selected by a GraphQL request
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub name: Option<String>,
+    pub operation_type: OperationType,
+    pub root_fields: Vec<String>,
+    /// hex encoded SHA-256 hash of the query document, as used by persisted queries
+    pub hash: String,
+    pub complexity: Complexity,
+}
+
+/// maximum nesting of selection sets and fragments in a query
+const MAX_NESTING: usize = 128;
+
+/// size of the operation's selection set, with fragments expanded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Complexity {
+    /// maximum nesting of fields, root fields are at depth 1
+    pub depth: usize,
+    /// total number of fields
+    pub field_count: usize,
+    /// number of aliased fields
+    pub alias_count: usize,
+}
+
+impl Operation {
+    /// parses the query to observe the requested operation
+    pub fn parse(query: &str, operation_name: Option<&str>) -> Result<Self, RequestError> {
+        let compiler = ApolloCompiler::new(query);
+
+        let ops = compiler.operations();
+        let operation = match operation_name {
+            None => ops.get(0),
+            Some(name) => ops.iter().find(|op| op.name().map_or(false, |n| n == name)),
+        };
+
+        let operation = match operation {
+            None => return Err(RequestError::OperationNotFound),
+            Some(op) => op,
+        };
+
+        Ok(Operation {
+            name: operation.name().map(|name| name.to_string()),
+            operation_type: operation.operation_ty(),
+            root_fields: operation
+                .fields(&compiler.db)
+                .iter()
+                .map(|field| field.name().to_string())
+                .collect(),
+            hash: hex::encode(sha2::Sha256::digest(query)),
+            complexity: Complexity::measure(&compiler, operation.selection_set())?,
+        })
+    }
+
+    /// true if the operation only selects introspection fields, that the router answers
+    /// without executing the query
+    pub fn is_introspection(&self) -> bool {
+        !self.root_fields.is_empty() && self.root_fields.iter().all(|field| field.starts_with("__"))
+    }
+
+    pub fn kind(&self) -> OperationKind {
+        match self.operation_type {
+            OperationType::Query => OperationKind::Query,
+            OperationType::Mutation => OperationKind::Mutation,
+            OperationType::Subscription => OperationKind::Subscription,
+        }
+    }
+
+    /// facts describing the operation, that can then be checked by the token:
+    /// - `operation_name("name")`, if the operation is named
+    /// - `operation_type("query")`
+    /// - `operation_hash("hash")`
+    /// - `query_depth(7)`, `field_count(120)` and `alias_count(3)`
+    /// - one `query("field")` or `mutation("field")` fact per root field
+    pub fn facts(&self) -> Vec<Fact> {
+        let operation_type = match self.operation_type {
+            OperationType::Query => "query",
+            OperationType::Mutation => "mutation",
+            OperationType::Subscription => "subscription",
+        };
+
+        let mut facts = Vec::new();
+        if let Some(name) = &self.name {
+            facts.push(fact!("operation_name({name})", name = name.as_str()));
+        }
+        facts.push(fact!(
+            "operation_type({operation_type})",
+            operation_type = operation_type
+        ));
+        facts.push(fact!("operation_hash({hash})", hash = self.hash.as_str()));
+        facts.push(fact!(
+            "query_depth({depth})",
+            depth = integer(self.complexity.depth)
+        ));
+        facts.push(fact!(
+            "field_count({count})",
+            count = integer(self.complexity.field_count)
+        ));
+        facts.push(fact!(
+            "alias_count({count})",
+            count = integer(self.complexity.alias_count)
+        ));
+
+        for field in &self.root_fields {
+            match self.operation_type {
+                OperationType::Query => facts.push(fact!("query({field})", field = field.as_str())),
+                OperationType::Mutation => {
+                    facts.push(fact!("mutation({field})", field = field.as_str()))
+                }
+                // subscriptions are not supported by the router
+                OperationType::Subscription => {}
+            }
+        }
+
+        facts
+    }
+}
+
+/// Datalog integers are 64 bits signed, the saturated counts of a huge query are clamped
+fn integer(count: usize) -> i64 {
+    i64::try_from(count).unwrap_or(i64::MAX)
+}
+
+impl Complexity {
+    fn measure(
+        compiler: &ApolloCompiler,
+        selection_set: &SelectionSet,
+    ) -> Result<Self, RequestError> {
+        Complexity::visit(
+            compiler,
+            selection_set,
+            &mut HashMap::new(),
+            &mut Vec::new(),
+        )
+    }
+
+    /// complexity of a selection set, with depths relative to it
+    ///
+    /// each fragment is measured once: spreading the same fragment many times at each level
+    /// would otherwise make the work exponential in the query size
+    fn visit(
+        compiler: &ApolloCompiler,
+        selection_set: &SelectionSet,
+        fragments: &mut HashMap<String, Complexity>,
+        nesting: &mut Vec<String>,
+    ) -> Result<Self, RequestError> {
+        if nesting.len() >= MAX_NESTING {
+            return Err(RequestError::TooNested);
+        }
+
+        let mut complexity = Complexity::default();
+        for selection in selection_set.selection() {
+            match selection {
+                Selection::Field(field) => {
+                    nesting.push(String::new());
+                    let fields =
+                        Complexity::visit(compiler, field.selection_set(), fragments, nesting)?;
+                    nesting.pop();
+
+                    complexity.add(&Complexity {
+                        depth: fields.depth + 1,
+                        field_count: fields.field_count.saturating_add(1),
+                        alias_count: fields
+                            .alias_count
+                            .saturating_add(field.alias().is_some() as usize),
+                    });
+                }
+                Selection::InlineFragment(fragment) => {
+                    nesting.push(String::new());
+                    let fragment =
+                        Complexity::visit(compiler, fragment.selection_set(), fragments, nesting)?;
+                    nesting.pop();
+
+                    complexity.add(&fragment);
+                }
+                Selection::FragmentSpread(spread) => {
+                    let name = spread.name().to_string();
+                    if let Some(fragment) = fragments.get(&name) {
+                        complexity.add(fragment);
+                        continue;
+                    }
+                    // fragment cycles are rejected by validation, but do not loop on them
+                    if nesting.contains(&name) {
+                        continue;
+                    }
+
+                    if let Some(fragment) = spread.fragment(&compiler.db) {
+                        nesting.push(name.clone());
+                        let fragment = Complexity::visit(
+                            compiler,
+                            fragment.selection_set(),
+                            fragments,
+                            nesting,
+                        )?;
+                        nesting.pop();
+
+                        complexity.add(&fragment);
+                        fragments.insert(name, fragment);
+                    }
+                }
+            }
+        }
+
+        Ok(complexity)
+    }
+
+    /// merges the complexity of selections at the same level
+    fn add(&mut self, other: &Complexity) {
+        self.depth = self.depth.max(other.depth);
+        self.field_count = self.field_count.saturating_add(other.field_count);
+        self.alias_count = self.alias_count.saturating_add(other.alias_count);
+    }
+}
+
+/// creates the authorizer for an operation
+///
+/// this is the common part of `Policies::authorize` and the `xtask` commands:
+/// the token, if present, is added afterwards
+pub fn authorizer(
+    operation: &Operation,
+    authorizer_code: &str,
+    trusted_keys: &HashMap<String, biscuit::PublicKey>,
+) -> Result<biscuit::Authorizer, BoxError> {
+    let mut authorizer = biscuit::Authorizer::new();
+    authorizer.add_code_with_params(authorizer_code, HashMap::new(), trusted_keys.clone())?;
+    authorizer.set_time();
+
+    for fact in operation.facts() {
+        authorizer.add_fact(fact)?;
+    }
+
+    Ok(authorizer)
+}
+
+/// outcome of an authorizer run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// the allow policy at this index matched and all checks succeeded
+    Allow(usize),
+    /// the deny policy at this index matched
+    Deny(usize),
+    /// some checks failed, contains the source of the failed checks
+    FailedChecks(Vec<String>),
+    /// no policy matched
+    NoMatchingPolicy,
+    /// the token does not match the `sealed` options
+    SealedToken(SealedToken),
+    /// the authorizer could not run
+    Error(String),
+}
+
+impl Decision {
+    pub fn new(result: &Result<usize, Token>) -> Self {
+        match result {
+            Ok(index) => Decision::Allow(*index),
+            Err(Token::FailedLogic(Logic::Unauthorized { checks, .. }))
+            | Err(Token::FailedLogic(Logic::NoMatchingPolicy { checks }))
+                if !checks.is_empty() =>
+            {
+                Decision::FailedChecks(checks.iter().map(failed_check_rule).collect())
+            }
+            Err(Token::FailedLogic(Logic::Unauthorized {
+                policy: MatchedPolicy::Deny(index),
+                ..
+            })) => Decision::Deny(*index),
+            Err(Token::FailedLogic(Logic::NoMatchingPolicy { .. })) => Decision::NoMatchingPolicy,
+            Err(e) => Decision::Error(e.to_string()),
+        }
+    }
+
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Decision::Allow(_))
+    }
+
+    /// human readable description, with the source of the matched policy
+    pub fn describe(&self, authorizer: &biscuit::Authorizer) -> String {
+        let (_, _, _, policies) = authorizer.dump();
+        let policy = |index: &usize| {
+            policies
+                .get(*index)
+                .map(|policy| policy.to_string())
+                .unwrap_or_default()
+        };
+
+        match self {
+            Decision::Allow(index) => format!("allowed by policy {index}: {}", policy(index)),
+            Decision::Deny(index) => format!("denied by policy {index}: {}", policy(index)),
+            Decision::FailedChecks(checks) => {
+                format!("denied by failed checks: {}", checks.join(", "))
+            }
+            Decision::NoMatchingPolicy => "denied: no matching policy".to_string(),
+            Decision::SealedToken(e) => format!("denied: {e}"),
+            Decision::Error(e) => format!("error: {e}"),
+        }
+    }
+}
+
+/// true if the token is sealed, and cannot be attenuated anymore
+pub fn is_sealed(token: &biscuit::Biscuit) -> bool {
+    matches!(token.container().proof, biscuit::crypto::TokenNext::Seal(_))
+}
+
+/// values of the `nonce("...")` facts, from any block of the token
+pub fn token_nonces(authorizer: &mut biscuit::Authorizer) -> Result<Vec<String>, BoxError> {
+    let facts: Vec<biscuit::builder::Fact> =
+        authorizer.query_all("nonce($nonce) <- nonce($nonce)")?;
+
+    Ok(facts
+        .into_iter()
+        .filter_map(|fact| match fact.predicate.terms.into_iter().next() {
+            Some(biscuit::builder::Term::Str(nonce)) => Some(nonce),
+            _ => None,
+        })
+        .collect())
+}
+
+/// date after which the checks loaded in the authorizer, from the token or the authorizer
+/// code, fail because of their comparisons of the `time` fact
+pub fn expires_at(authorizer: &biscuit::Authorizer) -> Option<SystemTime> {
+    let (_, _, checks, _) = authorizer.dump();
+    checks_expiry(&checks)
+}
+
+/// earliest date at which the checks fail because of the `time` fact
+///
+/// A check fails once all its queries are past their upper bounds on the `time` fact,
+/// like `$time < 2022-09-30T16:32:00Z`. Lower bounds, like not-before dates, do not
+/// make a check expire.
+pub fn checks_expiry(checks: &[Check]) -> Option<SystemTime> {
+    checks
+        .iter()
+        .filter_map(|check| {
+            // the check passes while any of its queries matches
+            check
+                .queries
+                .iter()
+                .map(|query| {
+                    time_bounds(query)
+                        .into_iter()
+                        .filter_map(|bound| match bound {
+                            TimeBound::Before(date) => Some(date),
+                            TimeBound::After(_) => None,
+                        })
+                        .min()
+                })
+                .collect::<Option<Vec<_>>>()?
+                .into_iter()
+                .max()
+        })
+        .min()
+}
+
+/// earliest date in the future at which the authorization result could change
+///
+/// The `time` fact can be compared with a date in many ways: directly, through a variable
+/// bound by another fact like `expiration($date)`, or in a disjunction. Every date of the
+/// facts, rules, checks and policies counts, so the bound can be earlier than needed, but
+/// never later.
+pub fn time_bound(authorizer: &biscuit::Authorizer) -> Option<SystemTime> {
+    let (facts, rules, checks, policies) = authorizer.dump();
+
+    let mut dates = Vec::new();
+    for fact in &facts {
+        fact.predicate
+            .terms
+            .iter()
+            .for_each(|term| term_dates(term, &mut dates));
+    }
+    for rule in rules
+        .iter()
+        .chain(checks.iter().flat_map(|check| check.queries.iter()))
+        .chain(policies.iter().flat_map(|policy| policy.queries.iter()))
+    {
+        let terms = rule
+            .head
+            .terms
+            .iter()
+            .chain(
+                rule.body
+                    .iter()
+                    .flat_map(|predicate| predicate.terms.iter()),
+            )
+            .chain(rule.expressions.iter().flat_map(|expression| {
+                expression.ops.iter().filter_map(|op| match op {
+                    Op::Value(term) => Some(term),
+                    _ => None,
+                })
+            }));
+        terms.for_each(|term| term_dates(term, &mut dates));
+    }
+
+    let now = SystemTime::now();
+    dates
+        .into_iter()
+        .map(|date| UNIX_EPOCH + Duration::from_secs(date))
+        .filter(|date| *date > now)
+        .min()
+}
+
+/// dates of a term, including the ones in sets
+fn term_dates(term: &Term, dates: &mut Vec<u64>) {
+    match term {
+        Term::Date(date) => dates.push(*date),
+        Term::Set(terms) => terms.iter().for_each(|term| term_dates(term, dates)),
+        _ => {}
+    }
+}
+
+/// true if the authorization failed only because of time checks
+pub fn expired(result: &Result<usize, Token>) -> bool {
+    match result {
+        Err(Token::FailedLogic(Logic::Unauthorized {
+            policy: MatchedPolicy::Allow(_),
+            checks,
+        })) => {
+            !checks.is_empty()
+                && checks
+                    .iter()
+                    .all(|check| is_time_check(&failed_check_rule(check)))
+        }
+        _ => false,
+    }
+}
+
+/// hash of the persisted query, for automatic persisted queries and persisted query lists
+pub fn persisted_query_hash(request: &graphql::Request) -> Option<&str> {
+    request
+        .extensions
+        .get("persistedQuery")
+        .and_then(|persisted_query| persisted_query.get("sha256Hash"))
+        .and_then(|hash| hash.as_str())
+}
+
+pub fn extract_token_string<B>(request: &http::Request<B>) -> Result<Option<&str>, RequestError> {
+    Ok(match request.headers().get("Authorization") {
+        None => None,
+        Some(value) => {
+            let value = value.to_str().map_err(|_| RequestError::InvalidHeader)?;
+            if !value.starts_with("Bearer ") {
+                return Err(RequestError::NotBearer);
+            }
+            Some(&value[7..])
+        }
+    })
+}
+
+pub fn extract_token<B>(
+    request: &http::Request<B>,
+    root: &biscuit::PublicKey,
+) -> Result<Option<biscuit::Biscuit>, BoxError> {
+    let opt_token_str = extract_token_string(request)?;
+
+    Ok(match opt_token_str {
+        None => None,
+        Some(s) => Some(biscuit::Biscuit::from_base64(s, root)?),
+    })
+}
+
+pub fn extract_unverified_token(
+    request: &http::Request<graphql::Request>,
+) -> Result<Option<biscuit::UnverifiedBiscuit>, BoxError> {
+    let opt_token_str = extract_token_string(request)?;
+
+    Ok(match opt_token_str {
+        None => None,
+        Some(s) => Some(biscuit::UnverifiedBiscuit::from_base64(s)?),
+    })
+}
+
+/// comparison of the `time` fact with a date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeBound {
+    /// the rule only matches before this date
+    Before(SystemTime),
+    /// the rule only matches after this date
+    After(SystemTime),
+}
+
+/// comparisons of the `time` fact with dates in the expressions of a rule
+fn time_bounds(rule: &Rule) -> Vec<TimeBound> {
+    let variables: Vec<&str> = rule
+        .body
+        .iter()
+        .filter(|predicate| predicate.name == "time")
+        .filter_map(|predicate| match predicate.terms.as_slice() {
+            [Term::Variable(variable)] => Some(variable.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut bounds = Vec::new();
+    for expression in &rule.expressions {
+        // the comparisons of a negation or a disjunction do not bound the time
+        let conditional = expression.ops.iter().any(|op| {
+            matches!(
+                op,
+                Op::Binary(Binary::Or) | Op::Unary(biscuit::builder::Unary::Negate)
+            )
+        });
+        if conditional {
+            continue;
+        }
+
+        for ops in expression.ops.windows(3) {
+            let bound = match ops {
+                [Op::Value(Term::Variable(variable)), Op::Value(Term::Date(date)), Op::Binary(op)]
+                    if variables.contains(&variable.as_str()) =>
+                {
+                    time_bound_of(op, false, *date)
+                }
+                [Op::Value(Term::Date(date)), Op::Value(Term::Variable(variable)), Op::Binary(op)]
+                    if variables.contains(&variable.as_str()) =>
+                {
+                    time_bound_of(op, true, *date)
+                }
+                _ => None,
+            };
+            bounds.extend(bound);
+        }
+    }
+    bounds
+}
+
+/// bound of `$time op date`, or of `date op $time` if `reversed`
+fn time_bound_of(op: &Binary, reversed: bool, date: u64) -> Option<TimeBound> {
+    let date = UNIX_EPOCH + Duration::from_secs(date);
+    match (op, reversed) {
+        (Binary::LessThan | Binary::LessOrEqual, false)
+        | (Binary::GreaterThan | Binary::GreaterOrEqual, true) => Some(TimeBound::Before(date)),
+        (Binary::GreaterThan | Binary::GreaterOrEqual, false)
+        | (Binary::LessThan | Binary::LessOrEqual, true) => Some(TimeBound::After(date)),
+        _ => None,
+    }
+}
+
+/// true if the check only looks at the `time` fact
+fn is_time_check(check: &str) -> bool {
+    match Check::try_from(check) {
+        Ok(check) => check.queries.iter().all(|query| {
+            !query.body.is_empty() && query.body.iter().all(|predicate| predicate.name == "time")
+        }),
+        Err(_) => false,
+    }
+}
+
+fn failed_check_rule(check: &FailedCheck) -> String {
+    match check {
+        FailedCheck::Block(check) => check.rule.clone(),
+        FailedCheck::Authorizer(check) => check.rule.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use biscuit::macros::biscuit;
+    use biscuit::macros::block;
+    use biscuit_auth as biscuit;
+
+    use super::*;
+
+    fn fact_strings(operation: &Operation) -> Vec<String> {
+        operation
+            .facts()
+            .iter()
+            .map(|fact| fact.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn root_operation_facts() {
+        let operation = Operation::parse(
+            "query A { me { id } } mutation B { createUser(name: \"a\") { id } }",
+            Some("B"),
+        )
+        .unwrap();
+        let facts = fact_strings(&operation);
+        assert!(facts.contains(&"mutation(\"createUser\")".to_string()));
+        assert!(!facts.contains(&"query(\"me\")".to_string()));
+
+        let operation = Operation::parse("{ me { id } test }", None).unwrap();
+        let facts = fact_strings(&operation);
+        assert!(facts.contains(&"query(\"me\")".to_string()));
+        assert!(facts.contains(&"query(\"test\")".to_string()));
+    }
+
+    #[test]
+    fn operation_facts() {
+        let query = "query A { me { id } } mutation B { createUser(name: \"a\") { id } }";
+        let operation = Operation::parse(query, Some("B")).unwrap();
+        assert_eq!(
+            fact_strings(&operation),
+            vec![
+                "operation_name(\"B\")".to_string(),
+                "operation_type(\"mutation\")".to_string(),
+                format!(
+                    "operation_hash(\"{}\")",
+                    hex::encode(sha2::Sha256::digest(query))
+                ),
+                "query_depth(2)".to_string(),
+                "field_count(2)".to_string(),
+                "alias_count(0)".to_string(),
+                "mutation(\"createUser\")".to_string(),
+            ]
+        );
+
+        let operation = Operation::parse("{ test }", None).unwrap();
+        let facts = fact_strings(&operation);
+        assert!(!facts.iter().any(|fact| fact.starts_with("operation_name(")));
+        assert!(facts.contains(&"operation_type(\"query\")".to_string()));
+    }
+
+    #[test]
+    fn complexity() {
+        let operation = Operation::parse(
+            "query {
+                me { id friends { ...friend } }
+                other: me { ... on User { id } }
+            }
+            fragment friend on User { id name friends { id } }",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            operation.complexity,
+            Complexity {
+                depth: 4,
+                field_count: 9,
+                alias_count: 1,
+            }
+        );
+
+        // each level spreads the next fragment twice: the fields are counted, not visited
+        let mut query = String::from("query { me { ...f0 } }");
+        for level in 0..40 {
+            query.push_str(&format!(
+                " fragment f{level} on User {{ friends {{ ...f{next} ...f{next} }} }}",
+                next = level + 1
+            ));
+        }
+        query.push_str(" fragment f40 on User { id }");
+        let operation = Operation::parse(&query, None).unwrap();
+        assert_eq!(operation.complexity.depth, 42);
+        assert_eq!(operation.complexity.field_count, 1 << 41);
+
+        let query = format!("query {{ {}id{} }}", "me { ".repeat(200), " }".repeat(200));
+        assert_eq!(
+            Operation::parse(&query, None).unwrap_err(),
+            RequestError::TooNested
+        );
+
+        // a fragment bomb just under the nesting limit counts more fields than a Datalog
+        // integer can hold
+        let mut query = String::from("query { me { ...f0 } }");
+        for level in 0..62 {
+            query.push_str(&format!(
+                " fragment f{level} on User {{ friends {{ ...f{next} ...f{next} }} }}",
+                next = level + 1
+            ));
+        }
+        query.push_str(" fragment f62 on User { id }");
+        let operation = Operation::parse(&query, None).unwrap();
+        assert_eq!(operation.complexity.field_count, 1 << 63);
+        assert!(fact_strings(&operation).contains(&format!("field_count({})", i64::MAX)));
+        let mut authorizer = authorizer(&operation, "allow if true;", &HashMap::new()).unwrap();
+        assert!(authorizer.authorize().is_ok());
+    }
+
+    #[test]
+    fn policy_decision() {
+        let code = std::fs::read_to_string("authorizer.datalog").unwrap();
+
+        let operation = Operation::parse("{ me { id } }", None).unwrap();
+        let mut denied = authorizer(&operation, &code, &HashMap::new()).unwrap();
+        assert_eq!(Decision::new(&denied.authorize()), Decision::Deny(2));
+
+        let operation = Operation::parse("{ test }", None).unwrap();
+        let mut allowed = authorizer(&operation, &code, &HashMap::new()).unwrap();
+        assert_eq!(Decision::new(&allowed.authorize()), Decision::Allow(3));
+    }
+
+    #[test]
+    fn explain_failed_check() {
+        let root = biscuit::KeyPair::new();
+        let code = std::fs::read_to_string("authorizer.datalog").unwrap();
+        let policies = Policies::builder(root.public(), code).build().unwrap();
+
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+        let token = token
+            .append(block!(
+                r#"check if query("me");
+                check if query("test") or
+                    query("other")"#
+            ))
+            .unwrap();
+
+        let body: graphql::Request =
+            serde_json::from_value(serde_json::json!({ "query": "{ me { id } }" })).unwrap();
+        let request = http::Request::builder()
+            .header(
+                "Authorization",
+                format!("Bearer {}", token.to_base64().unwrap()),
+            )
+            .body(body)
+            .unwrap();
+
+        let authorization = policies.authorize(&request).unwrap();
+        let explanation = policies.explain(&authorization);
+
+        assert!(explanation.facts.contains(&"query(\"me\")".to_string()));
+        assert_eq!(explanation.matched_policy, Some(1));
+        assert_eq!(explanation.checks.len(), 2);
+        assert_eq!(explanation.checks[0].block_id, Some(1));
+        assert!(explanation.checks[0].success);
+        assert_eq!(
+            explanation.checks[1].rule,
+            "check if query(\"test\") or query(\"other\")"
+        );
+        assert!(!explanation.checks[1].success);
+    }
+
+    #[test]
+    fn persisted_query() {
+        let root = biscuit::KeyPair::new();
+        let code = std::fs::read_to_string("authorizer.datalog").unwrap();
+        let policies = Policies::builder(root.public(), code).build().unwrap();
+
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+        let hash = hex::encode(sha2::Sha256::digest("{ test }"));
+        let token = token
+            .append(block!(
+                r#"check if persisted_query($hash), $hash == {hash}"#,
+                hash = hash.as_str()
+            ))
+            .unwrap();
+
+        let request = |body: serde_json::Value| {
+            http::Request::builder()
+                .header(
+                    "Authorization",
+                    format!("Bearer {}", token.to_base64().unwrap()),
+                )
+                .body(serde_json::from_value::<graphql::Request>(body).unwrap())
+                .unwrap()
+        };
+
+        let authorization = policies
+            .authorize(&request(serde_json::json!({
+                "query": "{ test }",
+                "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } }
+            })))
+            .unwrap();
+        let (facts, _, _, _) = authorization.authorizer.dump();
+        assert!(facts
+            .iter()
+            .any(|fact| fact.to_string() == format!("persisted_query(\"{hash}\")")));
+        assert_eq!(Decision::new(&authorization.result), Decision::Allow(1));
+
+        let authorization = policies
+            .authorize(&request(serde_json::json!({ "query": "{ test }" })))
+            .unwrap();
+        assert!(!Decision::new(&authorization.result).is_allowed());
+
+        // the client cannot claim the hash of another query
+        let e = policies
+            .authorize(&request(serde_json::json!({
+                "query": "{ me { id } }",
+                "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } }
+            })))
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<RequestError>(),
+            Some(&RequestError::PersistedQueryMismatch)
+        );
+
+        // a request without a query is an error, not a panic
+        let e = policies
+            .authorize(&request(serde_json::json!({
+                "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } }
+            })))
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<RequestError>(),
+            Some(&RequestError::MissingQuery)
+        );
+    }
+
+    #[test]
+    fn token_limits() {
+        let root = biscuit::KeyPair::new();
+        let mut limits = LimitsConf::default();
+        let policies = |limits: &LimitsConf| {
+            Policies::builder(root.public(), "allow if true;".to_string())
+                .limits(limits.clone())
+                .build()
+                .unwrap()
+        };
+
+        let token = biscuit!(r#"user(1); role("admin");"#).build(&root).unwrap();
+        let token = token
+            .append(block!(
+                r#"check if query("me"); is_user($id) <- user($id);"#
+            ))
+            .unwrap();
+        let request = http::Request::builder()
+            .header(
+                "Authorization",
+                format!("Bearer {}", token.to_base64().unwrap()),
+            )
+            .body(serde_json::from_value::<graphql::Request>(serde_json::json!({})).unwrap())
+            .unwrap();
+
+        assert!(policies(&limits).extract_token(&request).unwrap().is_some());
+
+        limits.max_facts = Some(2);
+        limits.max_rules = Some(1);
+        assert!(policies(&limits).extract_token(&request).unwrap().is_some());
+
+        limits.max_rules = Some(0);
+        assert_eq!(
+            policies(&limits)
+                .extract_token(&request)
+                .unwrap_err()
+                .downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded::Rules)
+        );
+
+        limits.max_rules = None;
+        limits.max_facts = Some(1);
+        assert_eq!(
+            policies(&limits)
+                .extract_token(&request)
+                .unwrap_err()
+                .downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded::Facts)
+        );
+
+        limits.max_blocks = Some(1);
+        assert_eq!(
+            policies(&limits)
+                .extract_token(&request)
+                .unwrap_err()
+                .downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded::Blocks)
+        );
+
+        limits.max_token_size = Some(16);
+        assert_eq!(
+            policies(&limits)
+                .extract_token(&request)
+                .unwrap_err()
+                .downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded::TokenSize)
+        );
+    }
+
+    #[test]
+    fn trusted_keys() {
+        let root = biscuit::KeyPair::new();
+        let orga = biscuit::KeyPair::new();
+        let policies = Policies::builder(
+            root.public(),
+            r#"allow if orga_service_admin(true) trusting {orga_admin};
+            deny if true;"#
+                .to_string(),
+        )
+        .trusted_keys(HashMap::from([("orga_admin".to_string(), orga.public())]))
+        .build()
+        .unwrap();
+        assert!(policies.policies[0].contains(&orga.public().to_bytes_hex()));
+
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+        let block = token
+            .third_party_request()
+            .unwrap()
+            .create_block(&orga.private(), block!("orga_service_admin(true);"))
+            .unwrap();
+        let token = token.append_third_party(orga.public(), block).unwrap();
+
+        let request = http::Request::builder()
+            .body(
+                serde_json::from_value::<graphql::Request>(
+                    serde_json::json!({ "query": "{ test }" }),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+        let authorization = policies
+            .authorize_token(&request, Some(token.clone()))
+            .unwrap();
+        assert_eq!(Decision::new(&authorization.result), Decision::Allow(0));
+
+        // the block is only trusted if it was signed by the configured key
+        let other = Policies::builder(root.public(), policies.code.clone())
+            .trusted_keys(HashMap::from([(
+                "orga_admin".to_string(),
+                biscuit::KeyPair::new().public(),
+            )]))
+            .build()
+            .unwrap();
+        let authorization = other.authorize_token(&request, Some(token)).unwrap();
+        assert_eq!(Decision::new(&authorization.result), Decision::Deny(1));
+    }
+
+    #[test]
+    fn sealed_tokens() {
+        let root = biscuit::KeyPair::new();
+        let policies = Policies::builder(root.public(), "allow if true;".to_string())
+            .sealed(SealedConf {
+                require: vec![OperationKind::Mutation],
+                ..SealedConf::default()
+            })
+            .build()
+            .unwrap();
+
+        let request = |query: &str| {
+            let body: graphql::Request =
+                serde_json::from_value(serde_json::json!({ "query": query })).unwrap();
+            http::Request::builder().body(body).unwrap()
+        };
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+        let sealed = token.seal().unwrap();
+        assert!(!is_sealed(&token));
+        assert!(is_sealed(&sealed));
+
+        let mutation = request("mutation { createUser(name: \"a\") { id } }");
+        let authorization = policies
+            .authorize_token(&mutation, Some(token.clone()))
+            .unwrap();
+        assert_eq!(authorization.sealed, Some(SealedToken::Required));
+        assert_eq!(
+            authorization.decision(),
+            Decision::SealedToken(SealedToken::Required)
+        );
+        let authorization = policies
+            .authorize_token(&mutation, Some(sealed.clone()))
+            .unwrap();
+        assert_eq!(authorization.decision(), Decision::Allow(0));
+        let authorization = policies
+            .authorize_token(&request("{ me { id } }"), Some(token))
+            .unwrap();
+        assert_eq!(authorization.decision(), Decision::Allow(0));
+
+        let policies = Policies::builder(root.public(), "allow if true;".to_string())
+            .sealed(SealedConf {
+                require: Vec::new(),
+                reject: true,
+            })
+            .build()
+            .unwrap();
+        let authorization = policies
+            .authorize_token(&request("{ me { id } }"), Some(sealed))
+            .unwrap();
+        assert_eq!(
+            authorization.decision(),
+            Decision::SealedToken(SealedToken::Rejected)
+        );
+    }
+
+    #[test]
+    fn time_bound() {
+        let root = biscuit::KeyPair::new();
+        let token = biscuit!(
+            r#"
+        check if time($time), $time < 2000-01-01T00:00:00Z;
+        check if time($time), $time < 2200-01-01T00:00:00Z;
+        check if time($time), 2100-01-01T00:00:00Z <= $time;"#
+        )
+        .build(&root)
+        .unwrap();
+
+        let mut authorizer = biscuit::Authorizer::new();
+        authorizer.add_code("allow if true;").unwrap();
+        assert_eq!(super::time_bound(&authorizer), None);
+
+        // the not-before date changes the result, past dates do not
+        authorizer.add_token(&token).unwrap();
+        assert_eq!(
+            super::time_bound(&authorizer),
+            Some(UNIX_EPOCH + Duration::from_secs(4102444800))
+        );
+
+        // the expiry is in a fact, and in a disjunction
+        let token = biscuit!(
+            r#"
+        expiration(2050-01-01T00:00:00Z);
+        check if time($time), expiration($date), $time < $date;
+        check if time($time), $time < 2060-01-01T00:00:00Z || $time < 2000-01-01T00:00:00Z;"#
+        )
+        .build(&root)
+        .unwrap();
+        let mut authorizer = biscuit::Authorizer::new();
+        authorizer.add_code("allow if true;").unwrap();
+        authorizer.add_token(&token).unwrap();
+        assert_eq!(
+            super::time_bound(&authorizer),
+            Some(UNIX_EPOCH + Duration::from_secs(2524608000))
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let root = biscuit::KeyPair::new();
+        let policies = Policies::builder(root.public(), "allow if true;".to_string())
+            .build()
+            .unwrap();
+
+        let body: graphql::Request =
+            serde_json::from_value(serde_json::json!({ "query": "{ me { id } }" })).unwrap();
+        let request = http::Request::builder().body(body).unwrap();
+
+        let token = biscuit!(
+            r#"
+        check if time($time), $time < 2000-01-01T00:00:00Z;
+        check if time($time), $time < 2100-01-01T00:00:00Z;"#
+        )
+        .build(&root)
+        .unwrap();
+        let authorization = policies
+            .authorize_token(&request, Some(token.clone()))
+            .unwrap();
+        assert!(expired(&authorization.result));
+        assert_eq!(
+            expires_at(&authorization.authorizer),
+            Some(UNIX_EPOCH + Duration::from_secs(946684800))
+        );
+
+        // a failing check that is not a time check is not an expiration
+        let expired_token = token.append(block!(r#"check if user(1);"#)).unwrap();
+        let authorization = policies
+            .authorize_token(&request, Some(expired_token))
+            .unwrap();
+        assert!(!expired(&authorization.result));
+        let expired_token = token
+            .append(block!(
+                r#"check if login_time($time), $time < 2000-01-01T00:00:00Z;"#
+            ))
+            .unwrap();
+        let authorization = policies
+            .authorize_token(&request, Some(expired_token))
+            .unwrap();
+        assert!(!expired(&authorization.result));
+
+        // not-before dates, other predicates and disjunctions are not expiration dates
+        let token = biscuit!(
+            r#"
+        check if time($time), $time > 2000-01-01T00:00:00Z;
+        check if login_time($time), $time < 2050-01-01T00:00:00Z;
+        check if time($time), $time < 2100-01-01T00:00:00Z or time($time), $time < 2150-01-01T00:00:00Z;
+        check if time($time), $time < 2120-01-01T00:00:00Z || $time > 2130-01-01T00:00:00Z;"#
+        )
+        .build(&root)
+        .unwrap();
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer.add_code("allow if true;").unwrap();
+        assert_eq!(
+            expires_at(&authorizer),
+            Some(UNIX_EPOCH + Duration::from_secs(5680281600))
+        );
+    }
+}
@@ -0,0 +1,479 @@
+use apollo_compiler::values::Value;
+use biscuit_auth as biscuit;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::BoxError;
+
+/// name of the plugin in the router configuration
+pub const PLUGIN_NAME: &str = "biscuit.auth";
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct Conf {
+    /// hex encoded public key verifying the tokens
+    pub public_root: String,
+    /// file containing the authorizer code
+    pub code: String,
+    /// file containing authorizer code evaluated alongside `code`, whose decisions
+    /// are logged but never enforced
+    pub shadow_code: Option<String>,
+    #[serde(default)]
+    pub mode: Mode,
+    /// runs the policies on introspection queries, that the router answers before the
+    /// execution service. When disabled, they are only controlled by the router's
+    /// `supergraph.introspection` option
+    #[serde(default = "default_authorize_introspection")]
+    pub authorize_introspection: bool,
+    /// caches authorization results, keyed by token and operation
+    pub cache: Option<CacheConf>,
+    /// facts derived from the HTTP request, added to the authorizer
+    #[serde(default)]
+    pub request_facts: RequestFactsConf,
+    /// limits the number of authorized requests per identity
+    pub rate_limit: Option<RateLimitConf>,
+    /// rejects the reuse of tokens containing a `nonce("...")` fact
+    pub nonce: Option<NonceConf>,
+    /// limits on the tokens and on the authorizer's execution
+    #[serde(default)]
+    pub limits: LimitsConf,
+    /// operations requiring sealed tokens, that cannot be attenuated further
+    #[serde(default)]
+    pub sealed: SealedConf,
+    /// file containing the hex encoded root private key, used to issue tokens, and to mint
+    /// subgraph tokens when the client's token cannot be attenuated
+    pub private_key_file: Option<String>,
+    /// options of the subgraphs, for all of them and by name
+    #[serde(default)]
+    pub subgraph: SubgraphsConf,
+    /// serves an endpoint exchanging upstream credentials for tokens
+    pub issuance: Option<IssuanceConf>,
+    /// serves an endpoint attenuating tokens on behalf of clients
+    pub attenuation: Option<AttenuationConf>,
+    /// serves an endpoint appending third party blocks to tokens on behalf of clients
+    pub third_party: Option<ThirdPartyConf>,
+    /// serves an admin endpoint describing the content of tokens
+    pub introspection: Option<IntrospectionConf>,
+    /// named third party public keys, like `orga_admin: ed25519/b8a7...`, referenced in the
+    /// authorizer code as `trusting {orga_admin}`
+    #[serde(default)]
+    pub trusted_keys: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct AttenuationConf {
+    /// address the endpoint listens on, it can be the supergraph's address
+    pub listen: SocketAddr,
+    /// path of the endpoint
+    #[serde(default = "default_attenuation_path")]
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct IssuanceConf {
+    /// address the endpoint listens on, it can be the supergraph's address
+    pub listen: SocketAddr,
+    /// path of the endpoint
+    #[serde(default = "default_issuance_path")]
+    pub path: String,
+    /// how the upstream credential is verified
+    pub credentials: CredentialsConf,
+    /// YAML file mapping each credential subject to the list of facts of its tokens'
+    /// authority block
+    pub facts: String,
+    /// validity of the tokens, in seconds
+    pub ttl: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialsConf {
+    /// YAML file mapping the API keys, sent in the `x-api-key` header, to subjects
+    ApiKeys(String),
+    /// cookie containing `subject.signature`, where the signature is the hex encoded
+    /// HMAC-SHA256 of the subject
+    SessionCookie {
+        /// name of the cookie
+        name: String,
+        /// file containing the HMAC secret
+        secret_file: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct LimitsConf {
+    /// maximum size of the base64 encoded token, in bytes, checked before parsing it
+    pub max_token_size: Option<usize>,
+    /// maximum number of blocks, including the authority block
+    pub max_blocks: Option<usize>,
+    /// maximum number of facts, across all blocks of the token
+    pub max_facts: Option<usize>,
+    /// maximum number of rules, across all blocks of the token
+    pub max_rules: Option<usize>,
+    /// maximum number of iterations of the authorizer's Datalog engine
+    pub max_iterations: Option<u64>,
+    /// maximum time spent running the authorizer, in microseconds
+    pub max_time_us: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct NonceConf {
+    /// how long a nonce is remembered, in seconds. It should be longer than the validity
+    /// of one-time tokens
+    pub ttl: u64,
+    /// maximum number of nonces remembered at once by the in-memory store
+    pub capacity: usize,
+    /// name of a store registered with `biscuit_router_plugin::nonce::register_store`,
+    /// used instead of the in-memory store
+    pub store: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct RateLimitConf {
+    /// identifies the client the limit applies to. Requests without identity are not limited
+    pub key: RateLimitKey,
+    /// maximum number of requests per identity in each interval. A token can set its own
+    /// limit with a `rate_limit(100)` fact in its authority block
+    pub requests: u64,
+    /// length of the interval, in seconds
+    pub interval: u64,
+    /// maximum number of identities tracked at once. The least recently seen identity is
+    /// evicted to make room, and its counter starts again from zero, so the capacity must be
+    /// larger than the number of identities active in an interval
+    #[serde(default = "default_rate_limit_capacity")]
+    pub capacity: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// Datalog rule run on the authorizer, the terms of the first result are the identity,
+    /// like `identity($id) <- user($id)`
+    Query(String),
+    /// revocation id of the token's authority block
+    RevocationId,
+}
+
+fn default_issuance_path() -> String {
+    "/biscuit/token".to_string()
+}
+
+fn default_attenuation_path() -> String {
+    "/biscuit/attenuate".to_string()
+}
+
+fn default_rate_limit_capacity() -> usize {
+    10_000
+}
+
+fn default_third_party_path() -> String {
+    "/biscuit/third-party".to_string()
+}
+
+fn default_introspection_path() -> String {
+    "/biscuit/introspect".to_string()
+}
+
+fn default_authorize_introspection() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct RequestFactsConf {
+    /// adds `client_name("name")` and `client_version("version")` facts from the
+    /// `apollographql-client-name` and `apollographql-client-version` headers
+    #[serde(default)]
+    pub client: bool,
+    /// adds a `method("GET")` fact
+    #[serde(default)]
+    pub method: bool,
+    /// header containing the client's IP address, like `x-forwarded-for`. The last address
+    /// of the header is used, as it is the one added by the closest proxy. Adds an
+    /// `ip("1.2.3.4")` fact, and for IPv4 addresses, an `ipv4(16909060)` fact with the
+    /// address as an integer
+    pub ip_header: Option<String>,
+    /// headers added as `header("name", "value")` facts
+    #[serde(default)]
+    pub headers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct CacheConf {
+    /// maximum number of cached authorization results
+    pub capacity: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// requests are rejected if the authorization fails
+    #[default]
+    Enforce,
+    /// requests are never rejected, the authorization result is recorded in the context, the logs
+    /// and the response extensions
+    Report,
+    /// the plugin does nothing
+    Disabled,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ThirdPartyConf {
+    /// address the endpoint listens on, it can be the supergraph's address
+    pub listen: SocketAddr,
+    /// path of the endpoint
+    #[serde(default = "default_third_party_path")]
+    pub path: String,
+    /// authorities that can sign third party blocks, by name
+    pub authorities: HashMap<String, AuthorityConf>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct AuthorityConf {
+    /// URL of the authority's third party block endpoint
+    pub url: String,
+    /// hex encoded public key of the authority, the blocks it returns must be signed by it
+    pub public_key: String,
+    /// maximum time to get a third party block from the authority, 10 seconds by default
+    pub timeout_ms: Option<u64>,
+}
+
+impl AuthorityConf {
+    pub fn public_key(&self) -> Result<biscuit::PublicKey, BoxError> {
+        Ok(biscuit::PublicKey::from_bytes_hex(&self.public_key)?)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.unwrap_or(10_000))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct SealedConf {
+    /// operation types that can only be run with a sealed token
+    #[serde(default)]
+    pub require: Vec<OperationKind>,
+    /// rejects sealed tokens for all operations
+    #[serde(default)]
+    pub reject: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationKind {
+    Query,
+    Mutation,
+    Subscription,
+}
+
+/// subgraph options, like the router's `all` and `subgraphs` sections of `headers`
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct SubgraphsConf {
+    /// options applying to all subgraphs
+    #[serde(default)]
+    pub all: SubgraphConf,
+    /// options of each subgraph, by name, overriding the ones of `all`
+    #[serde(default)]
+    pub subgraphs: HashMap<String, SubgraphConf>,
+}
+
+impl SubgraphsConf {
+    /// options of a subgraph, merged with the ones of `all`
+    pub fn get(&self, subgraph: &str) -> SubgraphConf {
+        let specific = self.subgraphs.get(subgraph);
+        SubgraphConf {
+            on_failure: specific
+                .and_then(|conf| conf.on_failure)
+                .or(self.all.on_failure),
+            forward_token: specific
+                .and_then(|conf| conf.forward_token)
+                .or(self.all.forward_token),
+        }
+    }
+
+    /// the options of all subgraphs, including `all`
+    pub fn all(&self) -> impl Iterator<Item = &SubgraphConf> {
+        std::iter::once(&self.all).chain(self.subgraphs.values())
+    }
+
+    /// rejects the options of subgraphs that are not in the supergraph, like a misspelled
+    /// name, since they would be silently ignored
+    pub fn check_names(&self, supergraph_sdl: &str) -> Result<(), BoxError> {
+        let names = subgraph_names(supergraph_sdl);
+        let mut unknown: Vec<&str> = self
+            .subgraphs
+            .keys()
+            .filter(|name| !names.contains(*name))
+            .map(String::as_str)
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+
+        unknown.sort_unstable();
+        Err(BoxError::from(format!(
+            "unknown subgraphs in the `subgraph` options: {}",
+            unknown.join(", ")
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct SubgraphConf {
+    /// what is sent to the subgraph when the client's token cannot be attenuated, or when
+    /// there is no token. By default, the subgraph request fails if the attenuation fails,
+    /// and no token is sent without a client token
+    pub on_failure: Option<SubgraphFallback>,
+    /// sends the client's token, attenuated, to the subgraph. When disabled, the
+    /// Authorization header is removed from the subgraph requests and `on_failure` is
+    /// ignored. Defaults to true
+    pub forward_token: Option<bool>,
+}
+
+impl SubgraphConf {
+    pub fn forwards_token(&self) -> bool {
+        self.forward_token.unwrap_or(true)
+    }
+}
+
+/// names of the subgraphs, from the `@join__graph(name: "...")` directives of the
+/// supergraph schema
+pub fn subgraph_names(supergraph_sdl: &str) -> Vec<String> {
+    let compiler = apollo_compiler::ApolloCompiler::new(supergraph_sdl);
+
+    let mut names = Vec::new();
+    for definition in compiler.db.enums().iter() {
+        if definition.name() != "join__Graph" {
+            continue;
+        }
+        for value in definition.enum_values_definition() {
+            for directive in value.directives() {
+                if directive.name() != "join__graph" {
+                    continue;
+                }
+                names.extend(directive.arguments().iter().filter_map(|argument| {
+                    match (argument.name(), argument.value()) {
+                        ("name", Value::String(name)) => Some(name.clone()),
+                        _ => None,
+                    }
+                }));
+            }
+        }
+    }
+
+    names
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SubgraphFallback {
+    /// the client's Authorization header is sent as is
+    ForwardUnattenuated,
+    /// no token is sent
+    Strip,
+    /// the subgraph request fails
+    Fail,
+    /// the router mints a token restricted to the subgraph, without any authority fact. This
+    /// needs `private_key_file`
+    MintAnonymous,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct IntrospectionConf {
+    /// address the endpoint listens on, it should not be reachable by clients
+    pub listen: SocketAddr,
+    /// path of the endpoint
+    #[serde(default = "default_introspection_path")]
+    pub path: String,
+    /// authorizer code run on the caller's token, like `allow if admin(true);`
+    pub admin_policy: String,
+}
+
+impl Conf {
+    /// loads the plugin's configuration from a router configuration file
+    pub fn from_router_config(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let file = std::fs::File::open(path)?;
+        let config: serde_yaml::Value = serde_yaml::from_reader(file)?;
+
+        let plugin = config
+            .get("plugins")
+            .and_then(|plugins| plugins.get(PLUGIN_NAME))
+            .ok_or_else(|| {
+                BoxError::from(format!(
+                    "no `{PLUGIN_NAME}` plugin in the router configuration"
+                ))
+            })?;
+
+        Ok(serde_yaml::from_value(plugin.clone())?)
+    }
+
+    pub fn public_root(&self) -> Result<biscuit::PublicKey, BoxError> {
+        Ok(biscuit::PublicKey::from_bytes_hex(&self.public_root)?)
+    }
+
+    /// loads the root private key from `private_key_file`, it must match the public root key
+    pub fn private_root(&self) -> Result<Option<biscuit::KeyPair>, BoxError> {
+        let path = match self.private_key_file.as_ref() {
+            None => return Ok(None),
+            Some(path) => path,
+        };
+
+        let private_key = std::fs::read_to_string(path)?;
+        let keypair =
+            biscuit::KeyPair::from(&biscuit::PrivateKey::from_bytes_hex(private_key.trim())?);
+        if keypair.public() != self.public_root()? {
+            return Err(BoxError::from(
+                "the private key does not match the public root key",
+            ));
+        }
+        Ok(Some(keypair))
+    }
+
+    /// parses the named third party keys, with or without the `ed25519/` prefix
+    pub fn trusted_keys(&self) -> Result<HashMap<String, biscuit::PublicKey>, BoxError> {
+        self.trusted_keys
+            .iter()
+            .map(|(name, key)| {
+                let hex = key.strip_prefix("ed25519/").unwrap_or(key);
+                let key = biscuit::PublicKey::from_bytes_hex(hex)
+                    .map_err(|e| BoxError::from(format!("invalid trusted key `{name}`: {e}")))?;
+                Ok((name.clone(), key))
+            })
+            .collect()
+    }
+
+    /// reads the authorizer policies from the `code` file
+    pub fn load_code(&self) -> Result<String, BoxError> {
+        read_code(&self.code)
+    }
+
+    /// reads the shadow policies from the `shadow_code` file
+    pub fn load_shadow_code(&self) -> Result<Option<String>, BoxError> {
+        self.shadow_code.as_ref().map(read_code).transpose()
+    }
+}
+
+impl LimitsConf {
+    /// run limits of the authorizer, the Biscuit defaults are used for the unset ones
+    pub fn authorizer_limits(&self) -> biscuit::AuthorizerLimits {
+        let defaults = biscuit::AuthorizerLimits::default();
+        biscuit::AuthorizerLimits {
+            max_facts: defaults.max_facts,
+            max_iterations: self.max_iterations.unwrap_or(defaults.max_iterations),
+            max_time: self
+                .max_time_us
+                .map(Duration::from_micros)
+                .unwrap_or(defaults.max_time),
+        }
+    }
+}
+
+fn read_code(path: impl AsRef<Path>) -> Result<String, BoxError> {
+    let mut code = String::new();
+    std::fs::File::open(path)?.read_to_string(&mut code)?;
+    Ok(code)
+}
@@ -0,0 +1,142 @@
+//! description of a token's content, for operators
+use biscuit_auth as biscuit;
+use serde::Serialize;
+
+use std::time::UNIX_EPOCH;
+
+use crate::authorization::checks_expiry;
+use crate::BoxError;
+
+/// content of a token, equivalent to `biscuit inspect`
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenDescription {
+    /// whether the token is signed by the root key
+    pub signature_valid: bool,
+    /// why the signature verification failed
+    pub signature_error: Option<String>,
+    pub blocks: Vec<BlockDescription>,
+    /// date after which the time checks of the token fail
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockDescription {
+    /// Datalog code of the block
+    pub source: String,
+    /// key that signed a third party block
+    pub external_key: Option<String>,
+    /// hex encoded revocation id
+    pub revocation_id: String,
+}
+
+/// describes a base64 encoded token, even if its signature is not valid
+pub fn describe(token: &str, root: &biscuit::PublicKey) -> Result<TokenDescription, BoxError> {
+    let unverified = biscuit::UnverifiedBiscuit::from_base64(token)?;
+
+    let (signature_valid, signature_error) = match biscuit::Biscuit::from_base64(token, root) {
+        Ok(_) => (true, None),
+        Err(e) => (false, Some(e.to_string())),
+    };
+
+    let revocation_ids = unverified.revocation_identifiers();
+    let external_keys = unverified.external_public_keys();
+
+    let mut blocks = Vec::new();
+    for index in 0..unverified.block_count() {
+        blocks.push(BlockDescription {
+            source: unverified.print_block_source(index)?,
+            external_key: external_keys
+                .get(index)
+                .cloned()
+                .flatten()
+                .map(|key| format!("ed25519/{}", hex::encode(key))),
+            revocation_id: revocation_ids
+                .get(index)
+                .map(hex::encode)
+                .unwrap_or_default(),
+        });
+    }
+
+    // the unverified blocks cannot be loaded in an authorizer, their checks are parsed back
+    let mut checks = Vec::new();
+    for block in &blocks {
+        let mut builder = biscuit::builder::BlockBuilder::new();
+        builder.add_code(&block.source)?;
+        checks.extend(builder.checks);
+    }
+
+    let expires_at = checks_expiry(&checks).map(|date| {
+        let seconds = date
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        biscuit::builder::Term::Date(seconds).to_string()
+    });
+
+    Ok(TokenDescription {
+        signature_valid,
+        signature_error,
+        blocks,
+        expires_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use biscuit::macros::biscuit;
+    use biscuit::macros::block;
+
+    use super::*;
+
+    #[test]
+    fn description() {
+        let root = biscuit::KeyPair::new();
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+        let token = token
+            .append(block!(
+                r#"check if time($time), $time < 2100-01-01T00:00:00Z;"#
+            ))
+            .unwrap();
+        let token = token.to_base64().unwrap();
+
+        let description = describe(&token, &root.public()).unwrap();
+        assert!(description.signature_valid);
+        assert_eq!(description.blocks.len(), 2);
+        assert!(description.blocks[0].source.contains("user(1)"));
+        assert_eq!(description.blocks[1].external_key, None);
+        assert_eq!(
+            description.expires_at.as_deref(),
+            Some("2100-01-01T00:00:00Z")
+        );
+
+        let description = describe(&token, &biscuit::KeyPair::new().public()).unwrap();
+        assert!(!description.signature_valid);
+        assert_eq!(description.blocks.len(), 2);
+    }
+
+    #[test]
+    fn not_before() {
+        let root = biscuit::KeyPair::new();
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+
+        // a check on the start of the validity period is not an expiry
+        let not_before = token
+            .append(block!(
+                r#"check if time($time), $time > 2020-01-01T00:00:00Z;"#
+            ))
+            .unwrap();
+        let description = describe(&not_before.to_base64().unwrap(), &root.public()).unwrap();
+        assert_eq!(description.expires_at, None);
+
+        let window = not_before
+            .append(block!(
+                r#"check if time($time), $time >= 2020-01-01T00:00:00Z, $time < 2100-01-01T00:00:00Z;"#
+            ))
+            .unwrap();
+        let description = describe(&window.to_base64().unwrap(), &root.public()).unwrap();
+        assert_eq!(
+            description.expires_at.as_deref(),
+            Some("2100-01-01T00:00:00Z")
+        );
+    }
+}
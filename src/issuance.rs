@@ -0,0 +1,237 @@
+//! exchange of upstream credentials for freshly minted tokens
+use biscuit::builder::Fact;
+use biscuit::macros::check;
+use biscuit_auth as biscuit;
+use hmac::Hmac;
+use hmac::Mac;
+use sha2::Sha256;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::config::CredentialsConf;
+use crate::config::IssuanceConf;
+use crate::BoxError;
+
+/// header containing the API key, with `credentials: api_keys`
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// mints tokens for the clients presenting a valid credential
+pub struct Issuer {
+    keypair: biscuit::KeyPair,
+    verifier: Verifier,
+    /// authority facts of each subject
+    facts: HashMap<String, Vec<Fact>>,
+    ttl: Duration,
+}
+
+/// verifies the upstream credential and returns the subject it was issued to
+enum Verifier {
+    /// API key to subject
+    ApiKeys(HashMap<String, String>),
+    SessionCookie {
+        name: String,
+        secret: Vec<u8>,
+    },
+}
+
+impl Issuer {
+    /// loads the credentials and the facts mapping. The tokens are signed by the root key
+    /// pair, loaded from the plugin's `private_key_file`
+    pub fn from_conf(conf: &IssuanceConf, root: &biscuit::KeyPair) -> Result<Self, BoxError> {
+        let keypair = biscuit::KeyPair::from(&root.private());
+
+        let verifier = match &conf.credentials {
+            CredentialsConf::ApiKeys(path) => {
+                Verifier::ApiKeys(serde_yaml::from_reader(std::fs::File::open(path)?)?)
+            }
+            CredentialsConf::SessionCookie { name, secret_file } => Verifier::SessionCookie {
+                name: name.clone(),
+                secret: std::fs::read_to_string(secret_file)?
+                    .trim()
+                    .as_bytes()
+                    .to_vec(),
+            },
+        };
+
+        let mapping: HashMap<String, Vec<String>> =
+            serde_yaml::from_reader(std::fs::File::open(&conf.facts)?)?;
+        let facts = mapping
+            .into_iter()
+            .map(|(subject, facts)| {
+                let facts = facts
+                    .iter()
+                    .map(|fact| Fact::try_from(fact.as_str()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((subject, facts))
+            })
+            .collect::<Result<HashMap<_, _>, BoxError>>()?;
+
+        // reject a ttl that cannot be added to the current date when loading the configuration
+        let ttl = Duration::from_secs(conf.ttl);
+        expiration(ttl)?;
+
+        Ok(Issuer {
+            keypair,
+            verifier,
+            facts,
+            ttl,
+        })
+    }
+
+    /// mints a token for the subject of the request's credential
+    pub fn issue<B>(&self, request: &http::Request<B>) -> Result<biscuit::Biscuit, BoxError> {
+        let subject = self.verifier.verify(request)?;
+        let facts = self
+            .facts
+            .get(&subject)
+            .ok_or_else(|| BoxError::from("no facts for this credential"))?;
+
+        let mut builder = biscuit::Biscuit::builder();
+        for fact in facts {
+            builder.add_fact(fact.clone())?;
+        }
+        let expiration = expiration(self.ttl)?;
+        builder.add_check(check!(
+            "check if time($time), $time < {expiration}",
+            expiration = expiration
+        ))?;
+
+        Ok(builder.build(&self.keypair)?)
+    }
+}
+
+impl fmt::Debug for Issuer {
+    // the private key and the credentials stay out of the logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Issuer")
+            .field("public_key", &self.keypair.public().to_bytes_hex())
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+impl Verifier {
+    fn verify<B>(&self, request: &http::Request<B>) -> Result<String, BoxError> {
+        match self {
+            Verifier::ApiKeys(keys) => {
+                let key = request
+                    .headers()
+                    .get(API_KEY_HEADER)
+                    .ok_or_else(|| BoxError::from("missing API key"))?
+                    .to_str()?;
+                keys.get(key)
+                    .cloned()
+                    .ok_or_else(|| BoxError::from("invalid API key"))
+            }
+            Verifier::SessionCookie { name, secret } => {
+                let cookie = cookie(request, name)
+                    .ok_or_else(|| BoxError::from("missing session cookie"))?;
+                verify_session(cookie, secret)
+            }
+        }
+    }
+}
+
+/// signs a subject for the session cookie: `subject.signature`, where the signature is
+/// the hex encoded HMAC-SHA256 of the subject
+pub fn sign_session(subject: &str, secret: &[u8]) -> Result<String, BoxError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret)?;
+    mac.update(subject.as_bytes());
+    Ok(format!(
+        "{subject}.{}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+/// expiration date of a token minted now, valid for `ttl`
+pub fn expiration(ttl: Duration) -> Result<SystemTime, BoxError> {
+    SystemTime::now()
+        .checked_add(ttl)
+        .ok_or_else(|| BoxError::from("the ttl is too large"))
+}
+
+/// returns the subject of a session cookie, if its signature is valid
+fn verify_session(cookie: &str, secret: &[u8]) -> Result<String, BoxError> {
+    let (subject, signature) = cookie
+        .rsplit_once('.')
+        .ok_or_else(|| BoxError::from("invalid session cookie"))?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret)?;
+    mac.update(subject.as_bytes());
+    mac.verify_slice(&hex::decode(signature)?)
+        .map_err(|_| BoxError::from("invalid session cookie"))?;
+
+    Ok(subject.to_string())
+}
+
+fn cookie<'a, B>(request: &'a http::Request<B>, name: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_cookie() {
+        let secret = b"secret";
+        let cookie = sign_session("alice", secret).unwrap();
+        assert_eq!(verify_session(&cookie, secret).unwrap(), "alice");
+        assert!(verify_session(&cookie, b"other secret").is_err());
+        assert!(verify_session(&cookie.replace("alice", "bob"), secret).is_err());
+
+        let request = http::Request::builder()
+            .header("Cookie", format!("theme=dark; session={cookie}"))
+            .body(())
+            .unwrap();
+        let verifier = Verifier::SessionCookie {
+            name: "session".to_string(),
+            secret: secret.to_vec(),
+        };
+        assert_eq!(verifier.verify(&request).unwrap(), "alice");
+    }
+
+    #[test]
+    fn issue() {
+        let root = biscuit::KeyPair::new();
+        let issuer = Issuer {
+            keypair: biscuit::KeyPair::from(&root.private()),
+            verifier: Verifier::ApiKeys(HashMap::from([("key1".to_string(), "alice".to_string())])),
+            facts: HashMap::from([(
+                "alice".to_string(),
+                vec![Fact::try_from("user(\"1\")").unwrap()],
+            )]),
+            ttl: Duration::from_secs(60),
+        };
+
+        let request = http::Request::builder()
+            .header(API_KEY_HEADER, "key1")
+            .body(())
+            .unwrap();
+        let token = issuer.issue(&request).unwrap();
+        assert!(token.print_block_source(0).unwrap().contains("user(\"1\")"));
+
+        let request = http::Request::builder()
+            .header(API_KEY_HEADER, "key2")
+            .body(())
+            .unwrap();
+        assert!(issuer.issue(&request).is_err());
+    }
+
+    #[test]
+    fn ttl_overflow() {
+        assert!(expiration(Duration::from_secs(60)).is_ok());
+        assert!(expiration(Duration::from_secs(u64::MAX)).is_err());
+    }
+}
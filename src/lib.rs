@@ -0,0 +1,12 @@
+//! Authorization logic shared by the router plugin and the `xtask` tooling
+pub mod attenuation;
+pub mod authorization;
+pub mod config;
+pub mod introspection;
+pub mod issuance;
+pub mod nonce;
+pub mod policy_test;
+pub mod request;
+pub mod third_party;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
@@ -0,0 +1,149 @@
+//! storage of the nonces of one-time tokens, to detect replays
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::BoxError;
+
+/// stores registered with [`register_store`], by name
+static STORES: Mutex<Vec<(String, Arc<dyn NonceStore>)>> = Mutex::new(Vec::new());
+
+/// records the nonces that were already used
+///
+/// The plugin uses [`MemoryNonceStore`] by default, a store shared between router instances
+/// can be provided by implementing this trait, and registering it with [`register_store`].
+///
+/// `insert` is called on the router's async executor, in the request path, so it must not
+/// block it: a store waiting on the network, like a Redis client, must run its blocking
+/// calls in `tokio::task::block_in_place`, which the router's multi-threaded runtime allows.
+pub trait NonceStore: fmt::Debug + Send + Sync {
+    /// records the nonces of a token for `ttl`
+    ///
+    /// returns `false` without recording any of them if one was already recorded, so the
+    /// nonces of a rejected token can still be used.
+    fn insert(&self, nonces: &[String], ttl: Duration) -> Result<bool, BoxError>;
+}
+
+/// makes a nonce store available to the plugin, as the `store` option of the `nonce`
+/// configuration
+///
+/// This must be called before the router starts, in a binary calling `apollo_router::main`.
+pub fn register_store(name: &str, store: Arc<dyn NonceStore>) {
+    let mut stores = STORES.lock().expect("nonce stores lock poisoned");
+    stores.retain(|(registered, _)| registered != name);
+    stores.push((name.to_string(), store));
+}
+
+/// store registered under this name
+pub fn registered_store(name: &str) -> Option<Arc<dyn NonceStore>> {
+    STORES
+        .lock()
+        .expect("nonce stores lock poisoned")
+        .iter()
+        .find(|(registered, _)| registered == name)
+        .map(|(_, store)| store.clone())
+}
+
+/// bounded in-memory nonce store
+///
+/// Nonces are only dropped once expired: when the store is full of unexpired nonces, new
+/// ones are refused, so `capacity` should be larger than the number of one-time tokens used
+/// during `ttl`.
+#[derive(Debug)]
+pub struct MemoryNonceStore {
+    capacity: usize,
+    nonces: Mutex<HashMap<String, Instant>>,
+}
+
+impl MemoryNonceStore {
+    pub fn new(capacity: usize) -> Self {
+        MemoryNonceStore {
+            capacity,
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl NonceStore for MemoryNonceStore {
+    fn insert(&self, nonces: &[String], ttl: Duration) -> Result<bool, BoxError> {
+        let now = Instant::now();
+        let mut stored = self.nonces.lock().expect("nonce store lock poisoned");
+
+        let used = nonces.iter().any(|nonce| {
+            stored
+                .get(nonce)
+                .map_or(false, |expiration| *expiration > now)
+        });
+        if used {
+            return Ok(false);
+        }
+
+        if stored.len() + nonces.len() > self.capacity {
+            stored.retain(|_, expiration| *expiration > now);
+        }
+        let new = nonces
+            .iter()
+            .filter(|nonce| !stored.contains_key(*nonce))
+            .count();
+        if stored.len() + new > self.capacity {
+            // dropping unexpired nonces would allow replays
+            return Err(BoxError::from("the nonce store is full"));
+        }
+
+        for nonce in nonces {
+            stored.insert(nonce.clone(), now + ttl);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nonces(nonces: &[&str]) -> Vec<String> {
+        nonces.iter().map(|nonce| nonce.to_string()).collect()
+    }
+
+    #[test]
+    fn reused_nonce() {
+        let store = MemoryNonceStore::new(3);
+        let ttl = Duration::from_secs(60);
+
+        assert!(store.insert(&nonces(&["a"]), ttl).unwrap());
+        assert!(!store.insert(&nonces(&["a"]), ttl).unwrap());
+
+        // no nonce is consumed when one of them was already used
+        assert!(!store.insert(&nonces(&["b", "a"]), ttl).unwrap());
+        assert!(store.insert(&nonces(&["b"]), ttl).unwrap());
+
+        // expired nonces can be used again
+        assert!(store.insert(&nonces(&["c"]), Duration::ZERO).unwrap());
+        assert!(store.insert(&nonces(&["c"]), ttl).unwrap());
+    }
+
+    #[test]
+    fn full_store() {
+        let store = MemoryNonceStore::new(2);
+        let ttl = Duration::from_secs(60);
+
+        assert!(store.insert(&nonces(&["a"]), Duration::ZERO).unwrap());
+        assert!(store.insert(&nonces(&["b"]), ttl).unwrap());
+        // the expired nonce makes room for the new one
+        assert!(store.insert(&nonces(&["c"]), ttl).unwrap());
+
+        // unexpired nonces are never dropped
+        assert!(store.insert(&nonces(&["d"]), ttl).is_err());
+        assert!(!store.insert(&nonces(&["b"]), ttl).unwrap());
+    }
+
+    #[test]
+    fn registered_stores() {
+        register_store("test", Arc::new(MemoryNonceStore::new(1)));
+        assert!(registered_store("test").is_some());
+        assert!(registered_store("other").is_none());
+    }
+}
@@ -1,170 +1,351 @@
-use apollo_compiler::values::OperationType;
+mod attenuation;
+mod cache;
+mod error;
+mod introspection;
+mod issuance;
+mod rate_limit;
+mod shadow;
+mod third_party;
+
 use apollo_router::graphql;
 use apollo_router::layers::ServiceBuilderExt;
 use apollo_router::plugin::Plugin;
 use apollo_router::plugin::PluginInit;
 use apollo_router::register_plugin;
+use apollo_router::services::execution;
 use apollo_router::services::subgraph;
 use apollo_router::services::supergraph;
+use apollo_router::services::transport;
+use apollo_router::Context;
+use apollo_router::Endpoint;
+use apollo_router::ListenAddr;
+use biscuit::macros::biscuit;
 use biscuit::macros::block;
 use biscuit_auth as biscuit;
-use schemars::JsonSchema;
+use biscuit_router_plugin::attenuation::unseal;
+use biscuit_router_plugin::authorization::expired;
+use biscuit_router_plugin::authorization::expires_at;
+use biscuit_router_plugin::authorization::extract_token;
+use biscuit_router_plugin::authorization::extract_token_string;
+use biscuit_router_plugin::authorization::extract_unverified_token;
+use biscuit_router_plugin::authorization::time_bound;
+use biscuit_router_plugin::authorization::token_nonces;
+use biscuit_router_plugin::authorization::Decision;
+use biscuit_router_plugin::authorization::Operation;
+use biscuit_router_plugin::authorization::Policies;
+use biscuit_router_plugin::authorization::PoliciesBuilder;
+use biscuit_router_plugin::config::Conf;
+use biscuit_router_plugin::config::Mode;
+use biscuit_router_plugin::config::SubgraphFallback;
+use biscuit_router_plugin::config::SubgraphsConf;
+use biscuit_router_plugin::nonce::registered_store;
+use biscuit_router_plugin::nonce::MemoryNonceStore;
+use biscuit_router_plugin::nonce::NonceStore;
+use futures::StreamExt;
+use hyper::body::HttpBody;
+use multimap::MultiMap;
 use serde::Deserialize;
+use serde::Serialize;
 use tower::BoxError;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
 
-use std::error::Error;
-use std::io::Read;
 use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use attenuation::AttenuationEndpoint;
+use cache::AuthorizationCache;
+use cache::CacheEntry;
+use cache::CacheKey;
+use error::AuthorizationError;
+use introspection::IntrospectionEndpoint;
+use issuance::IssuanceEndpoint;
+use rate_limit::RateLimiter;
+use shadow::Shadow;
+use third_party::ThirdPartyEndpoint;
+
+/// context key where the authorization result is recorded
+const REPORT_CONTEXT_KEY: &str = "biscuit::authorization";
+
+/// maximum size of the bodies of requests to the plugin's endpoints
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// error returned to clients when the token cannot be sent to a subgraph, the details are
+/// only logged
+const SUBGRAPH_TOKEN_ERROR: &str = "the token cannot be sent to the subgraph";
 
 #[derive(Debug, Clone)]
 struct Biscuit {
-    root: biscuit::PublicKey,
-    code: String,
+    policies: Policies,
+    shadow: Option<Shadow>,
+    mode: Mode,
+    /// runs the policies on introspection queries, that the router answers directly
+    authorize_introspection: bool,
+    cache: Option<Arc<AuthorizationCache>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// root key minting subgraph tokens when the client's token cannot be attenuated
+    private_root: Option<Arc<biscuit::KeyPair>>,
+    subgraph: SubgraphsConf,
+    /// records the nonces of one-time tokens, and how long they are kept
+    nonces: Option<(Arc<dyn NonceStore>, Duration)>,
+    issuance: Option<IssuanceEndpoint>,
+    attenuation: Option<AttenuationEndpoint>,
+    third_party: Option<ThirdPartyEndpoint>,
+    introspection: Option<IntrospectionEndpoint>,
+}
+
+/// authorization result recorded in the context, and in the response extensions in report mode
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Report {
+    allowed: bool,
+    error: Option<String>,
+    /// RFC 3339 date after which the token cannot be used
+    expires_at: Option<String>,
 }
 
 impl Biscuit {
-    /// called in the supergraph plugin
+    /// called in the execution plugin
+    ///
+    /// At this stage, the router has resolved persisted queries, and validated the operation
+    /// against the schema, so the query is always present and valid.
     ///
-    /// rejects a token if a check fails in the token
+    /// rejects a token if a check fails in the token, and returns the date after which its
+    /// time checks fail
     fn validate_request(
         &self,
-        request: &mut supergraph::Request,
-        authorizer_code: &str,
-    ) -> Result<(), BoxError> {
-        /*** Parse the query to observe the requested operation ***/
-        let compiler = apollo_compiler::ApolloCompiler::new(
-            &request
-                .supergraph_request
-                .body()
-                .query
-                .as_deref()
-                .expect("there should be a query by now"),
-        );
+        request: &http::Request<graphql::Request>,
+    ) -> Result<Option<SystemTime>, AuthorizationError> {
+        let token = self.policies.extract_token(request).map_err(log_error)?;
 
-        let ops = compiler.operations();
-        let operation = match request.supergraph_request.body().operation_name.as_ref() {
-            None => ops.get(0),
-            Some(name) => ops.iter().find(|op| op.name() == Some(name)),
+        let cache = match self.cache.as_ref() {
+            None => None,
+            Some(cache) => {
+                let facts = self.policies.request_facts(request).map_err(log_error)?;
+                Some((cache, CacheKey::new(request.body(), &facts, token.as_ref())))
+            }
         };
 
-        let operation = match operation {
+        let cached = cache.as_ref().and_then(|(cache, key)| cache.get(key));
+        let (decision, result, identity, nonces, expiry) = match cached {
+            Some(entry) => (
+                entry.decision,
+                entry.result,
+                entry.identity,
+                entry.nonces,
+                entry.expires_at,
+            ),
             None => {
-                return Err(Box::<dyn Error + Send + Sync>::from(
-                    "cannot find operation",
-                ))
-            }
-            Some(op) => op,
-        };
+                let mut authorization = self
+                    .policies
+                    .authorize_token(request, token)
+                    .map_err(log_error)?;
+                // the explanation is only built when debug events are enabled
+                tracing::debug!("{}", self.policies.explain(&authorization));
 
-        /*** Create the authorizer
-         *
-         * A fact will be added for each root operation, that can then be checked by the token
-         *  ***/
-        let mut authorizer = biscuit::Authorizer::new();
-        authorizer.add_code(authorizer_code)?;
-        authorizer.set_time();
-
-        let operation_type = operation.operation_ty();
-        for root_op in operation.fields(&compiler.db).iter() {
-            match operation_type {
-                OperationType::Query => {
-                    authorizer.add_fact(format!("query(\"{}\")", root_op.name()).as_str())?
-                }
-                OperationType::Mutation => {
-                    authorizer.add_fact(format!("mutation(\"{}\")", root_op.name()).as_str())?
+                let decision = authorization.decision();
+                let expiry = expires_at(&authorization.authorizer);
+                // the `sealed` options apply whatever the policies decided
+                let result = match (authorization.sealed, authorization.result.as_ref()) {
+                    (Some(e), _) => Err(AuthorizationError::SealedToken(e)),
+                    (None, Ok(_)) => Ok(()),
+                    (None, Err(_)) if expired(&authorization.result) => {
+                        Err(AuthorizationError::Expired { expires_at: expiry })
+                    }
+                    (None, Err(e)) => Err(AuthorizationError::from(e)),
+                };
+
+                // only authorized requests count against the rate limit
+                let identity = match self.rate_limiter.as_ref() {
+                    Some(rate_limiter) if result.is_ok() => rate_limiter
+                        .identify(&mut authorization.authorizer, authorization.token.as_ref())
+                        .map_err(log_error)?,
+                    _ => None,
+                };
+
+                let nonces = match self.nonces {
+                    Some(_) if result.is_ok() => {
+                        token_nonces(&mut authorization.authorizer).map_err(log_error)?
+                    }
+                    _ => Vec::new(),
+                };
+
+                // errors that are not an authorization decision, like run limits, are not cached
+                let cache = cache.filter(|_| !matches!(decision, Decision::Error(_)));
+                if let Some((cache, key)) = cache {
+                    cache.insert(
+                        key,
+                        CacheEntry {
+                            decision: decision.clone(),
+                            result: result.clone(),
+                            valid_until: time_bound(&authorization.authorizer),
+                            identity: identity.clone(),
+                            nonces: nonces.clone(),
+                            expires_at: expiry,
+                        },
+                    );
                 }
-                // not supported by the router
-                OperationType::Subscription => {}
+
+                (decision, result, identity, nonces, expiry)
             }
+        };
+
+        if let Some(shadow) = self.shadow.as_ref() {
+            shadow.evaluate(request, &decision);
         }
 
-        /*** Get the token from the request
-         *
-         * If there's no Authorization header, we can still apply the authorizer policies on an unauthenticated request
-         * ***/
-        let opt_token = extract_token(&request.supergraph_request, &self.root)?;
+        result?;
 
-        if let Some(token) = opt_token.as_ref() {
-            authorizer.add_token(token)?;
+        if let (Some(rate_limiter), Some(identity)) = (self.rate_limiter.as_ref(), identity) {
+            rate_limiter.check(&identity)?;
         }
 
-        let res = authorizer.authorize();
-        println!("authorizer result {:?}:\n{}", res, authorizer.print_world());
-        res?;
-        Ok(())
+        // the token signature was verified, and the request is authorized and not rate
+        // limited: one-time tokens are consumed now, so a rejected request can be retried
+        if let Some((store, ttl)) = self.nonces.as_ref() {
+            if !nonces.is_empty() && !store.insert(&nonces, *ttl).map_err(log_error)? {
+                return Err(AuthorizationError::TokenReused);
+            }
+        }
+
+        Ok(expiry)
+    }
+
+    /// validates the request and records the report in the context
+    ///
+    /// returns the error rejecting the request, which is only logged in report mode
+    fn check_request(
+        &self,
+        request: &http::Request<graphql::Request>,
+        context: &Context,
+    ) -> Result<Option<AuthorizationError>, BoxError> {
+        let result = self.validate_request(request);
+        let expiry = match result.as_ref() {
+            Ok(expires_at) => *expires_at,
+            Err(AuthorizationError::Expired { expires_at }) => *expires_at,
+            Err(_) => None,
+        };
+        context.insert(
+            REPORT_CONTEXT_KEY,
+            Report {
+                allowed: result.is_ok(),
+                error: result.as_ref().err().map(|e| e.to_string()),
+                expires_at: expiry.map(rfc3339),
+            },
+        )?;
+
+        match result {
+            Ok(_) => Ok(None),
+            Err(e) if self.mode == Mode::Report => {
+                tracing::warn!(
+                    code = e.code(),
+                    "report mode: the request would have been rejected: {e}"
+                );
+                Ok(None)
+            }
+            Err(e) => Ok(Some(e)),
+        }
     }
 
     /// called in the subgraph plugin
     ///
     /// this attenuates the client token before sending it to the subgraph, to make an attenuated token
-    /// that can only be used to query that subgraph
+    /// that can only be used to query that subgraph. If it cannot be attenuated, or there is no
+    /// token, the subgraph's `on_failure` option applies
     fn attenuate(
         &self,
         service_name: &str,
+        fallback: Option<SubgraphFallback>,
         request: &mut subgraph::Request,
     ) -> Result<(), BoxError> {
-        if let Ok(Some(token)) = extract_unverified_token(&request.supergraph_request) {
-            let attenuated_token = token.append(block!(
-                "check if subgraph({subgraph});",
-                subgraph = service_name,
-            ))?;
-
-            request.subgraph_request.headers_mut().insert(
-                "Authorization",
-                format!("Bearer {}", attenuated_token.to_base64()?).parse()?,
-            );
+        let token = match self.attenuated_token(service_name, request) {
+            Ok(Some(token)) => Some(token),
+            // without a client token, nothing is changed by default
+            Ok(None) => match fallback {
+                None | Some(SubgraphFallback::ForwardUnattenuated) => return Ok(()),
+                Some(SubgraphFallback::Strip) => None,
+                Some(SubgraphFallback::Fail) => return Err(BoxError::from("missing token")),
+                Some(SubgraphFallback::MintAnonymous) => Some(self.mint_anonymous(service_name)?),
+            },
+            Err(e) => match fallback {
+                None | Some(SubgraphFallback::Fail) => return Err(e),
+                Some(SubgraphFallback::ForwardUnattenuated) => {
+                    extract_token_string(&request.supergraph_request)
+                        .ok()
+                        .flatten()
+                        .map(String::from)
+                }
+                Some(SubgraphFallback::Strip) => None,
+                Some(SubgraphFallback::MintAnonymous) => Some(self.mint_anonymous(service_name)?),
+            },
+        };
+
+        let headers = request.subgraph_request.headers_mut();
+        match token {
+            Some(token) => {
+                headers.insert("Authorization", format!("Bearer {token}").parse()?);
+            }
+            None => {
+                headers.remove("Authorization");
+            }
         }
 
         Ok(())
     }
-}
 
-fn extract_token_string(
-    request: &http::Request<graphql::Request>,
-) -> Result<Option<&str>, BoxError> {
-    Ok(match request.headers().get("Authorization") {
-        None => None,
-        Some(value) => {
-            let value = value.to_str()?;
-            if !value.starts_with("Bearer ") {
-                return Err(Box::<dyn Error + Send + Sync>::from("not a bearer token"));
+    /// the client token, attenuated to the subgraph
+    fn attenuated_token(
+        &self,
+        service_name: &str,
+        request: &subgraph::Request,
+    ) -> Result<Option<String>, BoxError> {
+        let token = match extract_unverified_token(&request.supergraph_request)? {
+            None => return Ok(None),
+            Some(token) => token,
+        };
+
+        let block = block!("check if subgraph({subgraph});", subgraph = service_name,);
+        let attenuated_token = match token.append(block.clone()) {
+            Ok(token) => token.to_base64()?,
+            Err(biscuit::error::Token::AppendOnSealed) => {
+                self.mint_subgraph_token(request, block)?
             }
-            Some(&value[7..])
-        }
-    })
-}
+            Err(e) => return Err(e.into()),
+        };
 
-fn extract_token(
-    request: &http::Request<graphql::Request>,
-    root: &biscuit::PublicKey,
-) -> Result<Option<biscuit::Biscuit>, BoxError> {
-    let opt_token_str = extract_token_string(request)?;
-
-    println!("parsing token from: {:?}", opt_token_str);
-    Ok(match opt_token_str {
-        None => None,
-        Some(s) => Some(biscuit::Biscuit::from_base64(s, root)?),
-    })
-}
+        Ok(Some(attenuated_token))
+    }
 
-fn extract_unverified_token(
-    request: &http::Request<graphql::Request>,
-) -> Result<Option<biscuit::UnverifiedBiscuit>, BoxError> {
-    let opt_token_str = extract_token_string(request)?;
+    /// token without any authority fact, that can only be used to query the subgraph
+    fn mint_anonymous(&self, service_name: &str) -> Result<String, BoxError> {
+        let root = self.private_root.as_ref().ok_or_else(|| {
+            BoxError::from("anonymous tokens cannot be minted without a `private_key_file`")
+        })?;
+        let token =
+            biscuit!("check if subgraph({subgraph});", subgraph = service_name,).build(root)?;
 
-    println!("parsing token from: {:?}", opt_token_str);
-    Ok(match opt_token_str {
-        None => None,
-        Some(s) => Some(biscuit::UnverifiedBiscuit::from_base64(s)?),
-    })
-}
+        Ok(token.to_base64()?)
+    }
+
+    /// sealed tokens cannot be attenuated: the router mints a token with the same blocks,
+    /// and attenuates it instead
+    ///
+    /// The client token is verified first, so that the router never signs a forged token.
+    fn mint_subgraph_token(
+        &self,
+        request: &subgraph::Request,
+        block: biscuit::builder::BlockBuilder,
+    ) -> Result<String, BoxError> {
+        let root = self.private_root.as_ref().ok_or_else(|| {
+            BoxError::from("sealed tokens cannot be attenuated without a `private_key_file`")
+        })?;
+        let token = extract_token(&request.supergraph_request, &self.policies.root())?
+            .ok_or_else(|| BoxError::from("missing token"))?;
 
-#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
-struct Conf {
-    public_root: String,
-    code: String,
+        Ok(unseal(&token, root)?.append(block)?.to_base64()?)
+    }
 }
 
 // This plugin is a skeleton for doing authentication that requires a remote call.
@@ -173,23 +354,193 @@ impl Plugin for Biscuit {
     type Config = Conf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
-        let root = biscuit::PublicKey::from_bytes_hex(&init.config.public_root)?;
-        let mut code = String::new();
-        std::fs::File::open(init.config.code)?.read_to_string(&mut code)?;
+        let policies = Policies::from_conf(&init.config)?;
+        let root = policies.root();
+        let private_root = init.config.private_root()?;
+        init.config.subgraph.check_names(&init.supergraph_sdl)?;
+        let mints_anonymous = init
+            .config
+            .subgraph
+            .all()
+            .any(|subgraph| subgraph.on_failure == Some(SubgraphFallback::MintAnonymous));
+        if mints_anonymous && private_root.is_none() {
+            return Err(BoxError::from(
+                "`mint_anonymous` needs the root private key in `private_key_file`",
+            ));
+        }
+        let issuance = init
+            .config
+            .issuance
+            .as_ref()
+            .map(|issuance| {
+                let private_root = private_root.as_ref().ok_or_else(|| {
+                    BoxError::from(
+                        "token issuance needs the root private key in `private_key_file`",
+                    )
+                })?;
+                IssuanceEndpoint::new(issuance, private_root)
+            })
+            .transpose()?;
+        if init.config.sealed.reject && !init.config.sealed.require.is_empty() {
+            return Err(BoxError::from(
+                "sealed tokens cannot be both required and rejected",
+            ));
+        }
+        let shadow = match init.config.load_shadow_code()? {
+            None => None,
+            Some(code) => Some(Shadow::new(
+                PoliciesBuilder::from_conf(&init.config)?
+                    .code(code)
+                    .build()?,
+            )),
+        };
 
-        Ok(Biscuit { root, code })
+        Ok(Biscuit {
+            policies,
+            shadow,
+            mode: init.config.mode,
+            authorize_introspection: init.config.authorize_introspection,
+            cache: init
+                .config
+                .cache
+                .as_ref()
+                .map(|cache| Arc::new(AuthorizationCache::new(cache.capacity))),
+            rate_limiter: init
+                .config
+                .rate_limit
+                .as_ref()
+                .map(RateLimiter::new)
+                .transpose()?
+                .map(Arc::new),
+            private_root: private_root.map(Arc::new),
+            subgraph: init.config.subgraph.clone(),
+            nonces: init
+                .config
+                .nonce
+                .as_ref()
+                .map(|nonce| {
+                    let store: Arc<dyn NonceStore> = match nonce.store.as_deref() {
+                        None => Arc::new(MemoryNonceStore::new(nonce.capacity)),
+                        Some(name) => registered_store(name).ok_or_else(|| {
+                            BoxError::from(format!("unknown nonce store `{name}`"))
+                        })?,
+                    };
+                    Ok::<_, BoxError>((store, Duration::from_secs(nonce.ttl)))
+                })
+                .transpose()?,
+            issuance,
+            attenuation: init
+                .config
+                .attenuation
+                .as_ref()
+                .map(|attenuation| AttenuationEndpoint::new(attenuation, root)),
+            third_party: init
+                .config
+                .third_party
+                .as_ref()
+                .map(|third_party| ThirdPartyEndpoint::new(third_party, root))
+                .transpose()?,
+            introspection: init
+                .config
+                .introspection
+                .as_ref()
+                .map(|introspection| IntrospectionEndpoint::new(introspection, root))
+                .transpose()?,
+        })
     }
 
     fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if self.mode == Mode::Disabled {
+            return service;
+        }
+
         let this = self.clone();
+        let mode = self.mode;
         ServiceBuilder::new()
-            .checkpoint(move |mut request: supergraph::Request| {
-                match this.validate_request(&mut request, &this.code) {
-                    Ok(()) => Ok(ControlFlow::Continue(request)),
-                    Err(e) => Ok(ControlFlow::Break(
+            .map_response(move |response: supergraph::Response| {
+                // only the first response of the stream gets the report
+                let mut report = response
+                    .context
+                    .get::<_, Report>(REPORT_CONTEXT_KEY)
+                    .ok()
+                    .flatten();
+                response.map(move |stream| {
+                    stream
+                        .map(move |mut response| {
+                            let extension = match report.take() {
+                                Some(report) if mode == Mode::Report => {
+                                    Some(serde_json_bytes::json!({
+                                        "mode": "report",
+                                        "allowed": report.allowed,
+                                        "error": report.error,
+                                        "expires_at": report.expires_at,
+                                    }))
+                                }
+                                // clients learn when they need a new token
+                                Some(Report {
+                                    expires_at: Some(expires_at),
+                                    ..
+                                }) => Some(serde_json_bytes::json!({
+                                    "expires_at": expires_at,
+                                })),
+                                _ => None,
+                            };
+                            if let Some(extension) = extension {
+                                response.extensions.insert("biscuit".into(), extension);
+                            }
+                            response
+                        })
+                        .boxed()
+                })
+            })
+            .checkpoint(move |request: supergraph::Request| {
+                // the router answers introspection queries before the execution service
+                let introspection = this.authorize_introspection
+                    && is_introspection(request.supergraph_request.body());
+                if !introspection {
+                    return Ok(ControlFlow::Continue(request));
+                }
+
+                match this.check_request(&request.supergraph_request, &request.context)? {
+                    None => Ok(ControlFlow::Continue(request)),
+                    Some(e) => Ok(ControlFlow::Break(
                         supergraph::Response::error_builder()
-                            .error(graphql::Error::builder().message(e.to_string()).build())
-                            .status_code(http::StatusCode::UNAUTHORIZED)
+                            .error(e.graphql_error())
+                            .status_code(e.status_code())
+                            .context(request.context)
+                            .build()?,
+                    )),
+                }
+            })
+            .service(service)
+            .boxed()
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        if self.mode == Mode::Disabled {
+            return service;
+        }
+
+        let this = self.clone();
+        ServiceBuilder::new()
+            .checkpoint(move |request: execution::Request| {
+                // introspection queries were already authorized in the supergraph service
+                let authorized = request
+                    .context
+                    .get::<_, Report>(REPORT_CONTEXT_KEY)
+                    .ok()
+                    .flatten()
+                    .is_some();
+                if authorized {
+                    return Ok(ControlFlow::Continue(request));
+                }
+
+                match this.check_request(&request.supergraph_request, &request.context)? {
+                    None => Ok(ControlFlow::Continue(request)),
+                    Some(e) => Ok(ControlFlow::Break(
+                        execution::Response::error_builder()
+                            .error(e.graphql_error())
+                            .status_code(e.status_code())
                             .context(request.context)
                             .build()?,
                     )),
@@ -204,25 +555,152 @@ impl Plugin for Biscuit {
         service_name: &str,
         service: subgraph::BoxService,
     ) -> subgraph::BoxService {
+        if self.mode == Mode::Disabled {
+            return service;
+        }
+
+        let conf = self.subgraph.get(service_name);
+
+        // the client's token, even attenuated, must never reach these subgraphs
+        if !conf.forwards_token() {
+            return ServiceBuilder::new()
+                .map_request(|mut request: subgraph::Request| {
+                    request
+                        .subgraph_request
+                        .headers_mut()
+                        .remove("Authorization");
+                    request
+                })
+                .service(service)
+                .boxed();
+        }
+
         let this = self.clone();
         let service_name = service_name.to_string();
+        let fallback = conf.on_failure;
 
         ServiceBuilder::new()
             .checkpoint(move |mut request: subgraph::Request| {
-                match this.attenuate(&service_name, &mut request) {
+                match this.attenuate(&service_name, fallback, &mut request) {
                     Ok(()) => Ok(ControlFlow::Continue(request)),
-                    Err(e) => Ok(ControlFlow::Break(
-                        subgraph::Response::error_builder()
-                            .error(graphql::Error::builder().message(e.to_string()).build())
-                            .status_code(http::StatusCode::UNAUTHORIZED)
-                            .context(request.context)
-                            .build()?,
-                    )),
+                    Err(e) => {
+                        // the details, like token parse errors, are only logged
+                        tracing::warn!(
+                            subgraph = service_name.as_str(),
+                            "cannot send the token to the subgraph: {e}"
+                        );
+                        Ok(ControlFlow::Break(
+                            subgraph::Response::error_builder()
+                                .error(
+                                    graphql::Error::builder()
+                                        .message(SUBGRAPH_TOKEN_ERROR)
+                                        .build(),
+                                )
+                                .status_code(http::StatusCode::UNAUTHORIZED)
+                                .context(request.context)
+                                .build()?,
+                        ))
+                    }
                 }
             })
             .service(service)
             .boxed()
     }
+
+    fn web_endpoints(&self) -> MultiMap<ListenAddr, Endpoint> {
+        let mut endpoints = MultiMap::new();
+        if let Some(issuance) = self.issuance.as_ref() {
+            endpoints.insert(issuance.listen_addr(), issuance.endpoint());
+        }
+        if let Some(attenuation) = self.attenuation.as_ref() {
+            endpoints.insert(attenuation.listen_addr(), attenuation.endpoint());
+        }
+        if let Some(third_party) = self.third_party.as_ref() {
+            endpoints.insert(third_party.listen_addr(), third_party.endpoint());
+        }
+        if let Some(introspection) = self.introspection.as_ref() {
+            endpoints.insert(introspection.listen_addr(), introspection.endpoint());
+        }
+        endpoints
+    }
+}
+
+/// converts an error of the authorization path, logging the details that are not sent to
+/// the client
+fn log_error(e: BoxError) -> AuthorizationError {
+    let details = e.to_string();
+    let error = AuthorizationError::from(e);
+    match error {
+        AuthorizationError::Internal => tracing::error!("authorization error: {details}"),
+        _ => tracing::debug!("authorization error: {details}"),
+    }
+    error
+}
+
+/// true if the query only selects introspection fields, like `__schema`
+///
+/// The execution service parses the query again, so most queries are not parsed here: the
+/// router only answers queries selecting `__schema` or `__type`, and `__typename` alone is
+/// authorized in the execution service.
+fn is_introspection(request: &graphql::Request) -> bool {
+    request
+        .query
+        .as_deref()
+        .filter(|query| selects_schema(query))
+        .and_then(|query| Operation::parse(query, request.operation_name.as_deref()).ok())
+        .map_or(false, |operation| operation.is_introspection())
+}
+
+/// true if the query contains the `__schema` or `__type` names, without parsing it
+fn selects_schema(query: &str) -> bool {
+    query
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .any(|name| name == "__schema" || name == "__type")
+}
+
+/// formats a date like Biscuit does, as RFC 3339
+fn rfc3339(date: SystemTime) -> String {
+    let seconds = date
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    biscuit::builder::Term::Date(seconds).to_string()
+}
+
+/// reads the body of a request to the plugin's endpoints, refusing bodies larger than
+/// `MAX_BODY_SIZE`
+async fn read_body(mut body: hyper::Body) -> Result<Vec<u8>, BoxError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(BoxError::from("request body too large"));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// plain text response of the plugin's endpoints
+fn text_response(
+    status: http::StatusCode,
+    body: impl Into<hyper::Body>,
+) -> Result<transport::Response, BoxError> {
+    Ok(http::Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "text/plain")
+        .body(body.into())?)
+}
+
+/// JSON response of the plugin's endpoints
+fn json_response(
+    status: http::StatusCode,
+    body: &impl Serialize,
+) -> Result<transport::Response, BoxError> {
+    Ok(http::Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(body)?.into())?)
 }
 
 // This macro allows us to use it in our plugin registry!
@@ -233,6 +711,8 @@ register_plugin!("biscuit", "auth", Biscuit);
 mod tests {
     use apollo_router::graphql;
     use apollo_router::plugin::test::MockSubgraph;
+    use apollo_router::plugin::Plugin;
+    use apollo_router::plugin::PluginInit;
     use apollo_router::services::subgraph;
     use apollo_router::services::supergraph;
     use apollo_router::MockedSubgraphs;
@@ -241,10 +721,18 @@ mod tests {
     use biscuit::macros::biscuit;
     use biscuit::macros::block;
     use biscuit_auth as biscuit;
+    use biscuit_router_plugin::authorization::extract_token;
+    use biscuit_router_plugin::authorization::Policies;
+    use biscuit_router_plugin::authorization::RequestError;
+    use biscuit_router_plugin::authorization::SealedToken;
     use tower::BoxError;
     use tower::ServiceExt;
 
-    use crate::plugins::biscuit::extract_token;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::error::AuthorizationError;
+    use super::SUBGRAPH_TOKEN_ERROR;
 
     const SCHEMA: &'static str = r#"schema
     @core(feature: "https://specs.apollo.dev/core/v0.1")
@@ -397,6 +885,10 @@ type Organization
             .build()
             .unwrap();
         let mut streamed_response = test_harness.oneshot(request).await?;
+        assert_eq!(
+            streamed_response.response.status(),
+            http::StatusCode::FORBIDDEN
+        );
 
         let first_response = streamed_response
             .next_response()
@@ -404,45 +896,88 @@ type Organization
             .expect("couldn't get primary response");
 
         println!("first response: {:?}", first_response);
+        let error = first_response.errors.get(0).unwrap();
+        assert_eq!(error.message, "authorization failed");
         assert_eq!(
-            first_response.errors.get(0).unwrap().message,
-            "authorization failed"
+            serde_json::to_value(&error.extensions)?,
+            serde_json::json!({ "code": "UNAUTHORIZED" })
         );
 
         Ok(())
     }
 
-    fn validate(
-        root: biscuit::PublicKey,
-        service_name: &str,
-        request: &subgraph::Request,
-    ) -> Result<(), BoxError> {
-        let mut authorizer = authorizer!(
-            r#"
-        subgraph({subgraph});
-        allow if true
-"#,
-            subgraph = service_name
+    #[tokio::test]
+    async fn shadow_policies_are_not_enforced() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+
+        let mut subgraphs = MockedSubgraphs::default();
+        subgraphs.insert(
+            "user",
+            MockSubgraph::builder()
+                .with_json(
+                    serde_json::json! {{"query":"{test}"}},
+                    serde_json::json! {{"data": {"test": "ok"}}},
+                )
+                .build(),
         );
+        subgraphs.insert("organization", MockSubgraph::default());
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "include_subgraph_errors": {
+                    "all": true
+                },
+                "plugins": {
+                    "biscuit.auth": {
+                        "public_root": root_keypair.public().to_bytes_hex(),
+                        "code": "authorizer.datalog",
+                        "shadow_code": "authorizer.shadow.datalog",
+                    }
+                }
+            }))
+            .unwrap()
+            .schema(SCHEMA)
+            .extra_plugin(subgraphs)
+            .build()
+            .await
+            .unwrap();
 
-        let opt_token = extract_token(&request.subgraph_request, &root)?;
+        // the shadow policies deny this unauthenticated request, but the active ones allow it
+        let request = supergraph::Request::fake_builder()
+            .query("query { test }")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
 
-        if let Some(token) = opt_token.as_ref() {
-            authorizer.add_token(token)?;
-        }
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        println!("first response: {:?}", first_response);
+        assert!(first_response.errors.is_empty());
+        assert_eq!(
+            serde_json::to_value(&first_response.data)?,
+            serde_json::json!({ "test": "ok" })
+        );
 
-        let res = authorizer.authorize();
-        println!("authorizer:\n{}", authorizer.print_world());
-        res?;
         Ok(())
     }
 
     #[tokio::test]
-    async fn attenuation() -> Result<(), BoxError> {
+    async fn report_mode() -> Result<(), BoxError> {
         let root_keypair = biscuit::KeyPair::new();
-        let root_public = root_keypair.public();
 
-        //let subgraphs = plugin.subgraph_service(subgraph_name, service)
+        let mut subgraphs = MockedSubgraphs::default();
+        subgraphs.insert(
+            "user",
+            MockSubgraph::builder()
+                .with_json(
+                    serde_json::json! {{"query":"{test}"}},
+                    serde_json::json! {{"data": {"test": "ok"}}},
+                )
+                .build(),
+        );
+        subgraphs.insert("organization", MockSubgraph::default());
         let test_harness = TestHarness::builder()
             .configuration_json(serde_json::json!({
                 "include_subgraph_errors": {
@@ -452,69 +987,28 @@ type Organization
                     "biscuit.auth": {
                         "public_root": root_keypair.public().to_bytes_hex(),
                         "code": "authorizer.datalog",
+                        "mode": "report",
                     }
                 }
             }))
             .unwrap()
             .schema(SCHEMA)
-            .subgraph_hook(move |service_name, _| {
-
-                if service_name == "user" {
-                    tower::service_fn(move |request: subgraph::Request| async move {
-                        match validate(root_public, "usera", &request) {
-                            Err(e) => Ok(
-                                subgraph::Response::error_builder()
-                                    .error(graphql::Error::builder().message(e.to_string()).build())
-                                    .status_code(http::StatusCode::UNAUTHORIZED)
-                                    .context(request.context)
-                                    .build()?,
-                            ),
-                            Ok(()) => {
-                                    if request.subgraph_request.body().query.as_deref() == Some("{currentUser{activeOrganization{__typename id}}}") {
-                                        return Ok(subgraph::Response::fake_builder()
-                                        .data(serde_json::json!{{"data": {"currentUser": { "activeOrganization": null }}}})
-                                        .status_code(http::StatusCode::OK)
-                                        .context(request.context)
-                                        .build());
-                                    }
-                                panic!("unexpected")
-                            }
-                        }
-                    }).boxed()
-                } else if service_name == "organization" {
-                    tower::service_fn(move |request: subgraph::Request| async move {
-                        match validate(root_public, "organization", &request) {
-                            Err(e) => Ok(
-                                subgraph::Response::error_builder()
-                                    .error(graphql::Error::builder().message(e.to_string()).build())
-                                    .status_code(http::StatusCode::UNAUTHORIZED)
-                                    .context(request.context)
-                                    .build()?,
-                            ),
-                            Ok(()) => {
-                                todo!()
-                            }
-                        }
-                    }).boxed()
-                } else {
-                    panic!()
-                }
-
-            })
+            .extra_plugin(subgraphs)
             .build()
             .await
             .unwrap();
 
         let token = biscuit!(
-            r#"user(1);
-        "#
+            r#"
+        user(1);
+        check if query("me");"#
         )
         .build(&root_keypair)
         .unwrap();
 
         let request = supergraph::Request::fake_builder()
             .header("Authorization", format!("Bearer {}", token.to_base64()?))
-            .query("query { me { activeOrganization { id creatorUser { name } } } }")
+            .query("query { test }")
             .build()
             .unwrap();
         let mut streamed_response = test_harness.oneshot(request).await?;
@@ -526,8 +1020,965 @@ type Organization
 
         println!("first response: {:?}", first_response);
         assert_eq!(
-            first_response.errors.get(0).unwrap().message,
-            "authorization failed"
+            serde_json::to_value(&first_response.data)?,
+            serde_json::json!({ "test": "ok" })
+        );
+        assert_eq!(
+            serde_json::to_value(&first_response.extensions)?["biscuit"],
+            serde_json::json!({
+                "mode": "report",
+                "allowed": false,
+                "error": "authorization failed",
+                "expires_at": null,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn introspection_detection() {
+        let request = |query: &str| -> graphql::Request {
+            serde_json::from_value(serde_json::json!({ "query": query })).unwrap()
+        };
+
+        assert!(super::is_introspection(&request(
+            "{ __schema { types { name } } }"
+        )));
+        assert!(super::is_introspection(&request(
+            "{ __type(name: \"User\") { name } }"
+        )));
+        // `__typename` fields are not enough to parse the query
+        assert!(!super::selects_schema("{ me { __typename id } }"));
+        assert!(!super::is_introspection(&request(
+            "{ me { __typename id } }"
+        )));
+        assert!(!super::is_introspection(&request(
+            "{ __schema { types { name } } me { id } }"
+        )));
+    }
+
+    #[tokio::test]
+    async fn introspection_policies() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+
+        let token = biscuit!(
+            r#"
+        user(1);
+        check if query("me");"#
+        )
+        .build(&root_keypair)
+        .unwrap();
+
+        for authorize_introspection in [true, false] {
+            let test_harness = TestHarness::builder()
+                .configuration_json(serde_json::json!({
+                    "supergraph": {
+                        "introspection": true
+                    },
+                    "plugins": {
+                        "biscuit.auth": {
+                            "public_root": root_keypair.public().to_bytes_hex(),
+                            "code": "authorizer.datalog",
+                            "authorize_introspection": authorize_introspection,
+                        }
+                    }
+                }))
+                .unwrap()
+                .schema(SCHEMA)
+                .build()
+                .await
+                .unwrap();
+
+            let request = supergraph::Request::fake_builder()
+                .header("Authorization", format!("Bearer {}", token.to_base64()?))
+                .query("query { __schema { queryType { name } } }")
+                .build()
+                .unwrap();
+            let mut streamed_response = test_harness.oneshot(request).await?;
+
+            let first_response = streamed_response
+                .next_response()
+                .await
+                .expect("couldn't get primary response");
+
+            // the token's check fails on introspection queries
+            if authorize_introspection {
+                assert_eq!(
+                    first_response.errors.get(0).unwrap().message,
+                    "authorization failed"
+                );
+            } else {
+                assert!(first_response.errors.is_empty());
+                assert_eq!(
+                    serde_json::to_value(&first_response.data)?,
+                    serde_json::json!({ "__schema": { "queryType": { "name": "Query" } } })
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn token_expiry() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+
+        let mut subgraphs = MockedSubgraphs::default();
+        subgraphs.insert(
+            "user",
+            MockSubgraph::builder()
+                .with_json(
+                    serde_json::json! {{"query":"{test}"}},
+                    serde_json::json! {{"data": {"test": "ok"}}},
+                )
+                .build(),
+        );
+        subgraphs.insert("organization", MockSubgraph::default());
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "include_subgraph_errors": {
+                    "all": true
+                },
+                "plugins": {
+                    "biscuit.auth": {
+                        "public_root": root_keypair.public().to_bytes_hex(),
+                        "code": "authorizer.datalog",
+                    }
+                }
+            }))
+            .unwrap()
+            .schema(SCHEMA)
+            .extra_plugin(subgraphs)
+            .build()
+            .await
+            .unwrap();
+
+        let token = biscuit!(
+            r#"
+        user(1);
+        check if time($time), $time < 2100-01-01T00:00:00Z;"#
+        )
+        .build(&root_keypair)
+        .unwrap();
+        let request = supergraph::Request::fake_builder()
+            .header("Authorization", format!("Bearer {}", token.to_base64()?))
+            .query("query { test }")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.clone().oneshot(request).await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        assert!(first_response.errors.is_empty());
+        assert_eq!(
+            serde_json::to_value(&first_response.extensions)?["biscuit"],
+            serde_json::json!({ "expires_at": "2100-01-01T00:00:00Z" })
+        );
+
+        let token = biscuit!(
+            r#"
+        user(1);
+        check if time($time), $time < 2000-01-01T00:00:00Z;"#
+        )
+        .build(&root_keypair)
+        .unwrap();
+        let request = supergraph::Request::fake_builder()
+            .header("Authorization", format!("Bearer {}", token.to_base64()?))
+            .query("query { test }")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+        assert_eq!(
+            streamed_response.response.status(),
+            http::StatusCode::UNAUTHORIZED
+        );
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        let error = first_response.errors.get(0).unwrap();
+        assert_eq!(error.message, "token expired");
+        assert_eq!(
+            serde_json::to_value(&error.extensions)?,
+            serde_json::json!({
+                "code": "EXPIRED",
+                "expires_at": "2000-01-01T00:00:00Z",
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn error_mapping() {
+        let root = biscuit::KeyPair::new();
+        let token = biscuit!(r#"user(1);"#).build(&root).unwrap();
+
+        let e = biscuit::Biscuit::from_base64(
+            token.to_base64().unwrap(),
+            biscuit::KeyPair::new().public(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            AuthorizationError::from(&e),
+            AuthorizationError::InvalidToken
+        );
+
+        let authorize = |code: &str| {
+            let mut authorizer = token.authorizer().unwrap();
+            authorizer.add_code(code).unwrap();
+            authorizer.authorize().unwrap_err()
+        };
+        assert_eq!(
+            AuthorizationError::from(&authorize("deny if user(1);")),
+            AuthorizationError::Unauthorized
+        );
+        assert_eq!(
+            AuthorizationError::from(&authorize("check if user(2); allow if true;")),
+            AuthorizationError::Unauthorized
+        );
+        assert_eq!(
+            AuthorizationError::from(&authorize("allow if user(2);")),
+            AuthorizationError::NoMatchingPolicy
+        );
+
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer.add_code("allow if true;").unwrap();
+        authorizer.set_limits(biscuit::AuthorizerLimits {
+            max_facts: 0,
+            ..Default::default()
+        });
+        assert_eq!(
+            AuthorizationError::from(&authorizer.authorize().unwrap_err()),
+            AuthorizationError::RunLimit
+        );
+
+        // client mistakes are not internal errors
+        let e = AuthorizationError::from(BoxError::from(RequestError::NotBearer));
+        assert_eq!(e, AuthorizationError::InvalidToken);
+        assert_eq!(e.status_code(), http::StatusCode::UNAUTHORIZED);
+        let e = AuthorizationError::from(BoxError::from(RequestError::MissingQuery));
+        assert_eq!(
+            e,
+            AuthorizationError::InvalidRequest(RequestError::MissingQuery)
+        );
+        assert_eq!(e.to_string(), "missing query");
+        assert_eq!(e.status_code(), http::StatusCode::BAD_REQUEST);
+
+        // the details stay in the logs
+        let e = AuthorizationError::from(BoxError::from("the nonce store is full"));
+        assert_eq!(e, AuthorizationError::Internal);
+        assert_eq!(e.to_string(), "internal error");
+        assert_eq!(e.status_code(), http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn cached_authorization() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+
+        let conf = serde_json::from_value(serde_json::json!({
+            "public_root": root_keypair.public().to_bytes_hex(),
+            "code": "authorizer.datalog",
+            "cache": {
+                "capacity": 10
+            }
+        }))?;
+        let mut plugin =
+            super::Biscuit::new(PluginInit::new(conf, Arc::new(SCHEMA.to_string()))).await?;
+
+        let token = biscuit!(r#"user(1);"#).build(&root_keypair)?;
+        let body: graphql::Request = serde_json::from_value(serde_json::json!({
+            "query": "query { otherUser(id: 1) { name } }"
+        }))?;
+        let request = http::Request::builder()
+            .header("Authorization", format!("Bearer {}", token.to_base64()?))
+            .body(body)?;
+
+        assert_eq!(plugin.validate_request(&request), Ok(None));
+
+        // the authorizer is not run again for the same token and operation: the policies
+        // denying everything are not evaluated
+        plugin.policies =
+            Policies::builder(root_keypair.public(), "deny if true;".to_string()).build()?;
+        assert_eq!(plugin.validate_request(&request), Ok(None));
+
+        // another operation is evaluated with the new policies
+        let body: graphql::Request =
+            serde_json::from_value(serde_json::json!({ "query": "query { me { name } }" }))?;
+        let request = http::Request::builder()
+            .header("Authorization", format!("Bearer {}", token.to_base64()?))
+            .body(body)?;
+        assert_eq!(
+            plugin.validate_request(&request),
+            Err(AuthorizationError::Unauthorized)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cached_sealed_token() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+
+        let conf = serde_json::from_value(serde_json::json!({
+            "public_root": root_keypair.public().to_bytes_hex(),
+            "code": "authorizer.datalog",
+            "sealed": {
+                "require": ["query"]
+            },
+            "cache": {
+                "capacity": 10
+            }
+        }))?;
+        let mut plugin =
+            super::Biscuit::new(PluginInit::new(conf, Arc::new(SCHEMA.to_string()))).await?;
+
+        let token = biscuit!(r#"user(1);"#).build(&root_keypair)?;
+        let body: graphql::Request = serde_json::from_value(serde_json::json!({
+            "query": "query { otherUser(id: 1) { name } }"
+        }))?;
+        let request = http::Request::builder()
+            .header("Authorization", format!("Bearer {}", token.to_base64()?))
+            .body(body)?;
+
+        let denied = Err(AuthorizationError::SealedToken(SealedToken::Required));
+        assert_eq!(plugin.validate_request(&request), denied);
+
+        // the sealed token error is cached like the policies' decisions
+        plugin.policies =
+            Policies::builder(root_keypair.public(), "allow if true;".to_string()).build()?;
+        assert_eq!(plugin.validate_request(&request), denied);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cached_sealed_reject() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+
+        let conf = serde_json::from_value(serde_json::json!({
+            "public_root": root_keypair.public().to_bytes_hex(),
+            "code": "authorizer.datalog",
+            "sealed": {
+                "reject": true
+            },
+            "cache": {
+                "capacity": 10
+            }
+        }))?;
+        let plugin =
+            super::Biscuit::new(PluginInit::new(conf, Arc::new(SCHEMA.to_string()))).await?;
+
+        let token = biscuit!(r#"user(1);"#).build(&root_keypair)?;
+        let sealed = token.seal()?;
+        let request = |token: &biscuit::Biscuit| -> Result<_, BoxError> {
+            let body: graphql::Request = serde_json::from_value(serde_json::json!({
+                "query": "query { otherUser(id: 1) { name } }"
+            }))?;
+            Ok(http::Request::builder()
+                .header("Authorization", format!("Bearer {}", token.to_base64()?))
+                .body(body)?)
+        };
+
+        // both tokens have the same last revocation id, but not the same cached result
+        let rejected = Err(AuthorizationError::SealedToken(SealedToken::Rejected));
+        assert_eq!(plugin.validate_request(&request(&token)?), Ok(None));
+        assert_eq!(plugin.validate_request(&request(&sealed)?), rejected);
+        assert_eq!(plugin.validate_request(&request(&token)?), Ok(None));
+        assert_eq!(plugin.validate_request(&request(&sealed)?), rejected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limited() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+
+        let mut subgraphs = MockedSubgraphs::default();
+        subgraphs.insert(
+            "user",
+            MockSubgraph::builder()
+                .with_json(
+                    serde_json::json! {{"query":"{currentUser{activeOrganization{__typename id}}}"}},
+                    serde_json::json! {{"data": {"currentUser": { "activeOrganization": null }}}},
+                )
+                .build(),
+        );
+        subgraphs.insert("orga", MockSubgraph::default());
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "include_subgraph_errors": {
+                    "all": true
+                },
+                "plugins": {
+                    "biscuit.auth": {
+                        "public_root": root_keypair.public().to_bytes_hex(),
+                        "code": "authorizer.datalog",
+                        "rate_limit": {
+                            "key": { "query": "identity($id) <- user($id)" },
+                            "requests": 10,
+                            "interval": 3600
+                        }
+                    }
+                }
+            }))
+            .unwrap()
+            .schema(SCHEMA)
+            .extra_plugin(subgraphs)
+            .build()
+            .await
+            .unwrap();
+
+        // the token's own limit overrides the configured one
+        let token = biscuit!(
+            r#"
+        user(1);
+        rate_limit(1);"#
+        )
+        .build(&root_keypair)
+        .unwrap();
+
+        let request = || {
+            supergraph::Request::fake_builder()
+                .header(
+                    "Authorization",
+                    format!("Bearer {}", token.to_base64().unwrap()),
+                )
+                .query("query { me { activeOrganization { id creatorUser { name } } } }")
+                .build()
+                .unwrap()
+        };
+
+        let mut streamed_response = test_harness.clone().oneshot(request()).await?;
+        assert_eq!(streamed_response.response.status(), http::StatusCode::OK);
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        assert!(first_response.data.is_some());
+
+        let mut streamed_response = test_harness.clone().oneshot(request()).await?;
+        assert_eq!(
+            streamed_response.response.status(),
+            http::StatusCode::TOO_MANY_REQUESTS
+        );
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        assert_eq!(
+            first_response.errors.get(0).unwrap().message,
+            "rate limit exceeded"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replayed_nonce() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+
+        let mut subgraphs = MockedSubgraphs::default();
+        subgraphs.insert(
+            "user",
+            MockSubgraph::builder()
+                .with_json(
+                    serde_json::json! {{"query":"{currentUser{activeOrganization{__typename id}}}"}},
+                    serde_json::json! {{"data": {"currentUser": { "activeOrganization": null }}}},
+                )
+                .build(),
+        );
+        subgraphs.insert("orga", MockSubgraph::default());
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "include_subgraph_errors": {
+                    "all": true
+                },
+                "plugins": {
+                    "biscuit.auth": {
+                        "public_root": root_keypair.public().to_bytes_hex(),
+                        "code": "authorizer.datalog",
+                        "nonce": {
+                            "ttl": 3600,
+                            "capacity": 10
+                        }
+                    }
+                }
+            }))
+            .unwrap()
+            .schema(SCHEMA)
+            .extra_plugin(subgraphs)
+            .build()
+            .await
+            .unwrap();
+
+        let token = biscuit!(r#"user(1);"#).build(&root_keypair).unwrap();
+        let token = token.append(block!(r#"nonce("a1b2c3");"#)).unwrap();
+
+        let request = || {
+            supergraph::Request::fake_builder()
+                .header(
+                    "Authorization",
+                    format!("Bearer {}", token.to_base64().unwrap()),
+                )
+                .query("query { me { activeOrganization { id creatorUser { name } } } }")
+                .build()
+                .unwrap()
+        };
+
+        let mut streamed_response = test_harness.clone().oneshot(request()).await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        assert!(first_response.data.is_some());
+
+        let mut streamed_response = test_harness.clone().oneshot(request()).await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        assert_eq!(
+            first_response.errors.get(0).unwrap().message,
+            "token already used"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limited_nonce() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+
+        let conf = serde_json::from_value(serde_json::json!({
+            "public_root": root_keypair.public().to_bytes_hex(),
+            "code": "authorizer.datalog",
+            "rate_limit": {
+                "key": { "query": "identity($id) <- user($id)" },
+                "requests": 1,
+                "interval": 3600
+            },
+            "nonce": {
+                "ttl": 3600,
+                "capacity": 10
+            }
+        }))?;
+        let plugin =
+            super::Biscuit::new(PluginInit::new(conf, Arc::new(SCHEMA.to_string()))).await?;
+
+        let request = |token: &biscuit::Biscuit| -> Result<_, BoxError> {
+            let body: graphql::Request = serde_json::from_value(serde_json::json!({
+                "query": "query { otherUser(id: 1) { name } }"
+            }))?;
+            Ok(http::Request::builder()
+                .header("Authorization", format!("Bearer {}", token.to_base64()?))
+                .body(body)?)
+        };
+
+        let token = biscuit!(r#"user(1);"#).build(&root_keypair)?;
+        assert_eq!(plugin.validate_request(&request(&token)?), Ok(None));
+
+        let one_time = token.append(block!(r#"nonce("a1b2c3");"#))?;
+        assert_eq!(
+            plugin.validate_request(&request(&one_time)?),
+            Err(AuthorizationError::RateLimited)
+        );
+
+        // the nonce of the rate limited request was not consumed
+        let (store, ttl) = plugin.nonces.as_ref().unwrap();
+        assert!(store.insert(&["a1b2c3".to_string()], *ttl)?);
+
+        Ok(())
+    }
+
+    fn validate(
+        root: biscuit::PublicKey,
+        service_name: &str,
+        request: &subgraph::Request,
+    ) -> Result<(), BoxError> {
+        let mut authorizer = authorizer!(
+            r#"
+        subgraph({subgraph});
+        allow if true
+"#,
+            subgraph = service_name
+        );
+
+        let opt_token = extract_token(&request.subgraph_request, &root)?;
+
+        if let Some(token) = opt_token.as_ref() {
+            authorizer.add_token(token)?;
+        }
+
+        let res = authorizer.authorize();
+        println!("authorizer:\n{}", authorizer.print_world());
+        res?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn attenuation() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+        let root_public = root_keypair.public();
+
+        //let subgraphs = plugin.subgraph_service(subgraph_name, service)
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "include_subgraph_errors": {
+                    "all": true
+                },
+                "plugins": {
+                    "biscuit.auth": {
+                        "public_root": root_keypair.public().to_bytes_hex(),
+                        "code": "authorizer.datalog",
+                    }
+                }
+            }))
+            .unwrap()
+            .schema(SCHEMA)
+            .subgraph_hook(move |service_name, _| {
+
+                if service_name == "user" {
+                    tower::service_fn(move |request: subgraph::Request| async move {
+                        match validate(root_public, "usera", &request) {
+                            Err(e) => Ok(
+                                subgraph::Response::error_builder()
+                                    .error(graphql::Error::builder().message(e.to_string()).build())
+                                    .status_code(http::StatusCode::UNAUTHORIZED)
+                                    .context(request.context)
+                                    .build()?,
+                            ),
+                            Ok(()) => {
+                                    if request.subgraph_request.body().query.as_deref() == Some("{currentUser{activeOrganization{__typename id}}}") {
+                                        return Ok(subgraph::Response::fake_builder()
+                                        .data(serde_json::json!{{"data": {"currentUser": { "activeOrganization": null }}}})
+                                        .status_code(http::StatusCode::OK)
+                                        .context(request.context)
+                                        .build());
+                                    }
+                                panic!("unexpected")
+                            }
+                        }
+                    }).boxed()
+                } else if service_name == "organization" {
+                    tower::service_fn(move |request: subgraph::Request| async move {
+                        match validate(root_public, "organization", &request) {
+                            Err(e) => Ok(
+                                subgraph::Response::error_builder()
+                                    .error(graphql::Error::builder().message(e.to_string()).build())
+                                    .status_code(http::StatusCode::UNAUTHORIZED)
+                                    .context(request.context)
+                                    .build()?,
+                            ),
+                            Ok(()) => {
+                                todo!()
+                            }
+                        }
+                    }).boxed()
+                } else {
+                    panic!()
+                }
+
+            })
+            .build()
+            .await
+            .unwrap();
+
+        let token = biscuit!(
+            r#"user(1);
+        "#
+        )
+        .build(&root_keypair)
+        .unwrap();
+
+        let request = supergraph::Request::fake_builder()
+            .header("Authorization", format!("Bearer {}", token.to_base64()?))
+            .query("query { me { activeOrganization { id creatorUser { name } } } }")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        println!("first response: {:?}", first_response);
+        assert_eq!(
+            first_response.errors.get(0).unwrap().message,
+            "authorization failed"
+        );
+
+        Ok(())
+    }
+
+    /// sends `{ test }` to the `user` subgraph, with additional plugin and router options,
+    /// and returns the Authorization header it received, if it was called, and the errors
+    async fn subgraph_token(
+        root_keypair: &biscuit::KeyPair,
+        options: serde_json::Value,
+        router_options: serde_json::Value,
+        authorization: Option<&str>,
+    ) -> Result<(Option<Option<String>>, Vec<graphql::Error>), BoxError> {
+        let private_key_file = tempfile::NamedTempFile::new()?;
+        std::fs::write(
+            private_key_file.path(),
+            root_keypair.private().to_bytes_hex(),
+        )?;
+
+        let received = Arc::new(Mutex::new(None));
+        let hook_received = received.clone();
+        let mut plugin = serde_json::json!({
+            "public_root": root_keypair.public().to_bytes_hex(),
+            "code": "authorizer.datalog",
+            // invalid tokens reach the subgraphs
+            "mode": "report",
+            "private_key_file": private_key_file.path(),
+        });
+        if let (Some(plugin), serde_json::Value::Object(options)) =
+            (plugin.as_object_mut(), options)
+        {
+            plugin.extend(options);
+        }
+
+        let mut configuration = serde_json::json!({
+            "include_subgraph_errors": {
+                "all": true
+            },
+            "plugins": {
+                "biscuit.auth": plugin
+            }
+        });
+        if let (Some(configuration), serde_json::Value::Object(options)) =
+            (configuration.as_object_mut(), router_options)
+        {
+            configuration.extend(options);
+        }
+
+        let test_harness = TestHarness::builder()
+            .configuration_json(configuration)
+            .unwrap()
+            .schema(SCHEMA)
+            .subgraph_hook(move |service_name, service| {
+                if service_name != "user" {
+                    return service;
+                }
+                let received = hook_received.clone();
+                tower::service_fn(move |request: subgraph::Request| {
+                    let received = received.clone();
+                    async move {
+                        let authorization = request
+                            .subgraph_request
+                            .headers()
+                            .get("Authorization")
+                            .map(|value| value.to_str().unwrap().to_string());
+                        *received.lock().unwrap() = Some(authorization);
+                        Ok(subgraph::Response::fake_builder()
+                            .data(serde_json::json!({ "test": "ok" }))
+                            .context(request.context)
+                            .build())
+                    }
+                })
+                .boxed()
+            })
+            .build()
+            .await
+            .unwrap();
+
+        let mut request = supergraph::Request::fake_builder().query("query { test }");
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
+        }
+        let mut streamed_response = test_harness.oneshot(request.build().unwrap()).await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        let received = received.lock().unwrap().clone();
+        Ok((received, first_response.errors))
+    }
+
+    fn restricted_to_user(token: &str) -> bool {
+        let token = biscuit::UnverifiedBiscuit::from_base64(token.strip_prefix("Bearer ").unwrap())
+            .unwrap();
+        token
+            .print_block_source(token.block_count() - 1)
+            .unwrap()
+            .contains("check if subgraph(\"user\")")
+    }
+
+    /// what the `user` subgraph receives with the `on_failure` option
+    async fn fallback_token(
+        on_failure: &str,
+        authorization: Option<&str>,
+    ) -> Result<(Option<Option<String>>, Vec<graphql::Error>), BoxError> {
+        let options = serde_json::json!({
+            "subgraph": {
+                "subgraphs": {
+                    "user": {
+                        "on_failure": on_failure,
+                    }
+                }
+            }
+        });
+        subgraph_token(
+            &biscuit::KeyPair::new(),
+            options,
+            serde_json::json!({}),
+            authorization,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn subgraph_fallbacks() -> Result<(), BoxError> {
+        // without token
+        let (received, errors) = fallback_token("forward_unattenuated", None).await?;
+        assert_eq!(received, Some(None));
+        assert!(errors.is_empty());
+
+        let (received, errors) = fallback_token("strip", None).await?;
+        assert_eq!(received, Some(None));
+        assert!(errors.is_empty());
+
+        let (received, errors) = fallback_token("fail", None).await?;
+        assert_eq!(received, None);
+        assert_eq!(errors[0].message, SUBGRAPH_TOKEN_ERROR);
+
+        let (received, errors) = fallback_token("mint_anonymous", None).await?;
+        assert!(restricted_to_user(&received.flatten().unwrap()));
+        assert!(errors.is_empty());
+
+        // the token cannot be parsed, so it cannot be attenuated
+        let invalid = Some("Bearer invalid");
+        let (received, errors) = fallback_token("forward_unattenuated", invalid).await?;
+        assert_eq!(received, Some(Some("Bearer invalid".to_string())));
+        assert!(errors.is_empty());
+
+        let (received, errors) = fallback_token("strip", invalid).await?;
+        assert_eq!(received, Some(None));
+        assert!(errors.is_empty());
+
+        // the parse error is only logged
+        let (received, errors) = fallback_token("fail", invalid).await?;
+        assert_eq!(received, None);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, SUBGRAPH_TOKEN_ERROR);
+
+        let (received, errors) = fallback_token("mint_anonymous", invalid).await?;
+        assert!(restricted_to_user(&received.flatten().unwrap()));
+        assert!(errors.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn token_forwarding() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+        let token = biscuit!(r#"user(1);"#).build(&root_keypair).unwrap();
+        let authorization = format!("Bearer {}", token.to_base64()?);
+        let forward = |all: bool, subgraphs: serde_json::Value| {
+            serde_json::json!({
+                "subgraph": {
+                    "all": { "forward_token": all },
+                    "subgraphs": subgraphs,
+                }
+            })
+        };
+        let no_router_options = || serde_json::json!({});
+
+        // the subgraph is in the allow list
+        let options = forward(
+            false,
+            serde_json::json!({ "user": { "forward_token": true } }),
+        );
+        let (received, errors) = subgraph_token(
+            &root_keypair,
+            options,
+            no_router_options(),
+            Some(&authorization),
+        )
+        .await?;
+        assert!(restricted_to_user(&received.flatten().unwrap()));
+        assert!(errors.is_empty());
+
+        // the subgraph is not in the allow list
+        let options = forward(
+            false,
+            serde_json::json!({ "organization": { "forward_token": true } }),
+        );
+        let (received, errors) = subgraph_token(
+            &root_keypair,
+            options,
+            no_router_options(),
+            Some(&authorization),
+        )
+        .await?;
+        assert_eq!(received, Some(None));
+        assert!(errors.is_empty());
+
+        // the subgraph gets no token, even with a fallback
+        let options = forward(
+            true,
+            serde_json::json!({
+                "user": { "forward_token": false, "on_failure": "mint_anonymous" }
+            }),
+        );
+        let (received, errors) = subgraph_token(
+            &root_keypair,
+            options,
+            no_router_options(),
+            Some(&authorization),
+        )
+        .await?;
+        assert_eq!(received, Some(None));
+        assert!(errors.is_empty());
+
+        // nor when the router propagates the client's header
+        let propagate = serde_json::json!({
+            "headers": {
+                "all": {
+                    "request": [ { "propagate": { "named": "authorization" } } ]
+                }
+            }
+        });
+        let options = forward(
+            true,
+            serde_json::json!({ "user": { "forward_token": false } }),
+        );
+        let (received, errors) =
+            subgraph_token(&root_keypair, options, propagate, Some(&authorization)).await?;
+        assert_eq!(received, Some(None));
+        assert!(errors.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unknown_subgraphs() -> Result<(), BoxError> {
+        let root_keypair = biscuit::KeyPair::new();
+        let conf = |subgraph: &str| {
+            serde_json::from_value::<super::Conf>(serde_json::json!({
+                "public_root": root_keypair.public().to_bytes_hex(),
+                "code": "authorizer.datalog",
+                "subgraph": {
+                    "subgraphs": { subgraph: { "forward_token": false } }
+                }
+            }))
+        };
+
+        let init = PluginInit::new(conf("organization")?, Arc::new(SCHEMA.to_string()));
+        assert!(super::Biscuit::new(init).await.is_ok());
+
+        // a misspelled name would silently forward the token
+        let init = PluginInit::new(conf("organisation")?, Arc::new(SCHEMA.to_string()));
+        let e = super::Biscuit::new(init).await.err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "unknown subgraphs in the `subgraph` options: organisation"
         );
 
         Ok(())
@@ -0,0 +1,80 @@
+use apollo_router::services::transport;
+use apollo_router::Endpoint;
+use apollo_router::ListenAddr;
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::attenuation::restrict;
+use biscuit_router_plugin::attenuation::AttenuationRequest;
+use biscuit_router_plugin::attenuation::AttenuationResponse;
+use biscuit_router_plugin::config::AttenuationConf;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::json_response;
+use super::read_body;
+use super::text_response;
+
+/// router endpoint attenuating tokens for clients that cannot run Biscuit
+///
+/// No private key is needed: the token is only verified with the root public key, then a
+/// block is appended to it.
+#[derive(Debug, Clone)]
+pub(crate) struct AttenuationEndpoint {
+    listen: SocketAddr,
+    path: String,
+    root: biscuit::PublicKey,
+}
+
+impl AttenuationEndpoint {
+    pub(crate) fn new(conf: &AttenuationConf, root: biscuit::PublicKey) -> Self {
+        AttenuationEndpoint {
+            listen: conf.listen,
+            path: conf.path.clone(),
+            root,
+        }
+    }
+
+    pub(crate) fn listen_addr(&self) -> ListenAddr {
+        ListenAddr::from(self.listen)
+    }
+
+    pub(crate) fn endpoint(&self) -> Endpoint {
+        let root = self.root;
+        let service = tower::service_fn(move |request: transport::Request| async move {
+            if request.method() != http::Method::POST {
+                return text_response(
+                    http::StatusCode::METHOD_NOT_ALLOWED,
+                    "expected a POST request",
+                );
+            }
+
+            match attenuate(request, &root).await {
+                Ok(response) => json_response(http::StatusCode::OK, &response),
+                Err(e) => text_response(http::StatusCode::BAD_REQUEST, e.to_string()),
+            }
+        });
+
+        Endpoint::new(self.path.clone(), service.boxed())
+    }
+}
+
+async fn attenuate(
+    request: transport::Request,
+    root: &biscuit::PublicKey,
+) -> Result<AttenuationResponse, BoxError> {
+    let body = read_body(request.into_body()).await?;
+    let request: AttenuationRequest = serde_json::from_slice(&body)?;
+
+    let token = biscuit::Biscuit::from_base64(&request.token, root)?;
+    let token = restrict(
+        &token,
+        &request.operations,
+        Duration::from_secs(request.ttl),
+    )?;
+
+    Ok(AttenuationResponse {
+        token: token.to_base64()?,
+    })
+}
@@ -0,0 +1,144 @@
+use apollo_router::graphql;
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::authorization::is_sealed;
+use biscuit_router_plugin::authorization::persisted_query_hash;
+use biscuit_router_plugin::authorization::Decision;
+use lru::LruCache;
+use sha2::Digest;
+use sha2::Sha256;
+
+use super::error::AuthorizationError;
+use super::rate_limit::Identity;
+
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// bounded cache of authorization results
+///
+/// The cache belongs to the plugin instance, so it is emptied when the router
+/// reloads its configuration and the policies.
+#[derive(Debug)]
+pub(crate) struct AuthorizationCache {
+    entries: Mutex<LruCache<CacheKey, CacheEntry>>,
+}
+
+/// the authorization result only depends on the token, the operation and the facts derived
+/// from the HTTP request: variables are not part of the key because no fact is generated
+/// from them
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    /// revocation id of the token's last block, which is derived from its signature
+    token: Option<Vec<u8>>,
+    /// a sealed token has the same last revocation id as the token it was sealed from, but
+    /// not the same result with the `sealed` options
+    sealed: bool,
+    /// hash of the operation name, the query, the persisted query hash and the request facts
+    operation: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CacheEntry {
+    pub(crate) decision: Decision,
+    pub(crate) result: Result<(), AuthorizationError>,
+    /// earliest future date of the token and the authorizer code, after which time checks
+    /// could change the result
+    pub(crate) valid_until: Option<SystemTime>,
+    /// identity the rate limit applies to
+    pub(crate) identity: Option<Identity>,
+    /// nonces of the token, checked again on every use
+    pub(crate) nonces: Vec<String>,
+    /// date after which the time checks fail
+    pub(crate) expires_at: Option<SystemTime>,
+}
+
+impl CacheKey {
+    pub(crate) fn new(
+        request: &graphql::Request,
+        request_facts: &[biscuit::builder::Fact],
+        token: Option<&biscuit::Biscuit>,
+    ) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(request.operation_name.as_deref().unwrap_or_default());
+        hasher.update([0]);
+        hasher.update(request.query.as_deref().unwrap_or_default());
+        hasher.update([0]);
+        hasher.update(persisted_query_hash(request).unwrap_or_default());
+        for fact in request_facts {
+            hasher.update([0]);
+            hasher.update(fact.to_string());
+        }
+
+        CacheKey {
+            token: token.and_then(|token| token.revocation_identifiers().pop()),
+            sealed: token.map_or(false, is_sealed),
+            operation: hasher.finalize().to_vec(),
+        }
+    }
+}
+
+impl AuthorizationCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        AuthorizationCache {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<CacheEntry> {
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        let entry = entries.get(key)?.clone();
+
+        match entry.valid_until {
+            Some(date) if date <= SystemTime::now() => {
+                entries.pop(key);
+                None
+            }
+            _ => Some(entry),
+        }
+    }
+
+    pub(crate) fn insert(&self, key: CacheKey, entry: CacheEntry) {
+        self.entries
+            .lock()
+            .expect("cache lock poisoned")
+            .put(key, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn entry(valid_until: Option<SystemTime>) -> CacheEntry {
+        CacheEntry {
+            decision: Decision::Allow(0),
+            result: Ok(()),
+            valid_until,
+            identity: None,
+            nonces: Vec::new(),
+            expires_at: None,
+        }
+    }
+
+    fn key(query: &str) -> CacheKey {
+        let request: graphql::Request =
+            serde_json::from_value(serde_json::json!({ "query": query })).unwrap();
+        CacheKey::new(&request, &[], None)
+    }
+
+    #[test]
+    fn time_bound() {
+        let cache = AuthorizationCache::new(10);
+        let now = SystemTime::now();
+
+        cache.insert(key("{ a }"), entry(None));
+        cache.insert(key("{ b }"), entry(Some(now + Duration::from_secs(60))));
+        cache.insert(key("{ c }"), entry(Some(now - Duration::from_secs(1))));
+
+        assert!(cache.get(&key("{ a }")).is_some());
+        assert!(cache.get(&key("{ b }")).is_some());
+        // the time checks could give another result now
+        assert!(cache.get(&key("{ c }")).is_none());
+    }
+}
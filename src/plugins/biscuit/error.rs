@@ -0,0 +1,171 @@
+use apollo_router::graphql;
+use biscuit::error::Logic;
+use biscuit::error::Token;
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::authorization::LimitExceeded;
+use biscuit_router_plugin::authorization::RequestError;
+use biscuit_router_plugin::authorization::SealedToken;
+use tower::BoxError;
+
+use std::fmt;
+use std::time::SystemTime;
+
+use super::rate_limit::RateLimited;
+use super::rfc3339;
+
+/// error returned to clients when a request is rejected
+///
+/// The message is safe to show to clients: the details of the Biscuit errors, like the
+/// failing checks or the parse errors, are only logged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AuthorizationError {
+    /// the token could not be parsed, or its signature is invalid, or the `Authorization`
+    /// header does not contain a bearer token
+    InvalidToken,
+    /// the query is missing or cannot be authorized
+    InvalidRequest(RequestError),
+    /// a deny policy matched, or checks failed
+    Unauthorized,
+    /// checks succeeded but no policy matched
+    NoMatchingPolicy,
+    /// the only failing checks are time checks
+    Expired {
+        expires_at: Option<SystemTime>,
+    },
+    /// the token does not match the `sealed` options
+    SealedToken(SealedToken),
+    /// the token exceeds the configured limits
+    LimitExceeded(LimitExceeded),
+    /// the authorizer reached its run limits
+    RunLimit,
+    /// the authorizer code or the facts added to it could not be parsed
+    InvalidPolicy,
+    /// the token's nonce was already used
+    TokenReused,
+    RateLimited,
+    /// any other error, like a full nonce store
+    Internal,
+}
+
+impl AuthorizationError {
+    pub(crate) fn status_code(&self) -> http::StatusCode {
+        match self {
+            AuthorizationError::InvalidToken
+            | AuthorizationError::Expired { .. }
+            | AuthorizationError::TokenReused => http::StatusCode::UNAUTHORIZED,
+            AuthorizationError::Unauthorized
+            | AuthorizationError::NoMatchingPolicy
+            | AuthorizationError::SealedToken(_) => http::StatusCode::FORBIDDEN,
+            AuthorizationError::InvalidRequest(_)
+            | AuthorizationError::LimitExceeded(_)
+            | AuthorizationError::RunLimit => http::StatusCode::BAD_REQUEST,
+            AuthorizationError::RateLimited => http::StatusCode::TOO_MANY_REQUESTS,
+            AuthorizationError::InvalidPolicy | AuthorizationError::Internal => {
+                http::StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// value of the `code` extension of the GraphQL error
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            AuthorizationError::InvalidToken => "INVALID_TOKEN",
+            AuthorizationError::InvalidRequest(_) => "INVALID_REQUEST",
+            AuthorizationError::Unauthorized => "UNAUTHORIZED",
+            AuthorizationError::NoMatchingPolicy => "NO_MATCHING_POLICY",
+            AuthorizationError::Expired { .. } => "EXPIRED",
+            AuthorizationError::SealedToken(SealedToken::Required) => "SEALED_TOKEN_REQUIRED",
+            AuthorizationError::SealedToken(SealedToken::Rejected) => "SEALED_TOKEN_REJECTED",
+            AuthorizationError::LimitExceeded(_) => "LIMIT_EXCEEDED",
+            AuthorizationError::RunLimit => "RUN_LIMIT",
+            AuthorizationError::InvalidPolicy => "INVALID_POLICY",
+            AuthorizationError::TokenReused => "TOKEN_REUSED",
+            AuthorizationError::RateLimited => "RATE_LIMITED",
+            AuthorizationError::Internal => "INTERNAL_SERVER_ERROR",
+        }
+    }
+
+    pub(crate) fn graphql_error(&self) -> graphql::Error {
+        let mut error = graphql::Error::builder().message(self.to_string()).build();
+        error.extensions.insert("code", self.code().into());
+        if let AuthorizationError::Expired {
+            expires_at: Some(expires_at),
+        } = self
+        {
+            error
+                .extensions
+                .insert("expires_at", rfc3339(*expires_at).into());
+        }
+        error
+    }
+}
+
+impl fmt::Display for AuthorizationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthorizationError::InvalidToken => write!(f, "invalid token"),
+            AuthorizationError::InvalidRequest(e) => write!(f, "{e}"),
+            AuthorizationError::Unauthorized => write!(f, "authorization failed"),
+            AuthorizationError::NoMatchingPolicy => write!(f, "no policy matched"),
+            AuthorizationError::Expired { .. } => write!(f, "token expired"),
+            AuthorizationError::SealedToken(e) => write!(f, "{e}"),
+            AuthorizationError::LimitExceeded(e) => write!(f, "{e}"),
+            AuthorizationError::RunLimit => write!(f, "authorization limits reached"),
+            AuthorizationError::InvalidPolicy => write!(f, "invalid authorization policies"),
+            AuthorizationError::TokenReused => write!(f, "token already used"),
+            AuthorizationError::RateLimited => write!(f, "rate limit exceeded"),
+            AuthorizationError::Internal => write!(f, "internal error"),
+        }
+    }
+}
+
+impl std::error::Error for AuthorizationError {}
+
+impl From<&Token> for AuthorizationError {
+    fn from(e: &Token) -> Self {
+        match e {
+            Token::Format(_) | Token::Base64(_) => AuthorizationError::InvalidToken,
+            // a rule of the token that is not allowed in its block
+            Token::FailedLogic(Logic::InvalidBlockRule(..)) => AuthorizationError::InvalidToken,
+            Token::FailedLogic(Logic::Unauthorized { .. }) => AuthorizationError::Unauthorized,
+            Token::FailedLogic(Logic::NoMatchingPolicy { .. }) => {
+                AuthorizationError::NoMatchingPolicy
+            }
+            Token::RunLimit(_) => AuthorizationError::RunLimit,
+            Token::Language(_) => AuthorizationError::InvalidPolicy,
+            _ => AuthorizationError::Internal,
+        }
+    }
+}
+
+impl From<RequestError> for AuthorizationError {
+    fn from(e: RequestError) -> Self {
+        if e.is_header_error() {
+            AuthorizationError::InvalidToken
+        } else {
+            AuthorizationError::InvalidRequest(e)
+        }
+    }
+}
+
+/// errors from the authorization path are logged by the caller, the client gets the safe
+/// message
+impl From<BoxError> for AuthorizationError {
+    fn from(e: BoxError) -> Self {
+        if let Some(e) = e.downcast_ref::<Token>() {
+            AuthorizationError::from(e)
+        } else if let Some(e) = e.downcast_ref::<RequestError>() {
+            AuthorizationError::from(*e)
+        } else if let Some(e) = e.downcast_ref::<LimitExceeded>() {
+            AuthorizationError::LimitExceeded(*e)
+        } else {
+            AuthorizationError::Internal
+        }
+    }
+}
+
+impl From<RateLimited> for AuthorizationError {
+    fn from(_: RateLimited) -> Self {
+        AuthorizationError::RateLimited
+    }
+}
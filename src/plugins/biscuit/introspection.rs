@@ -0,0 +1,104 @@
+use apollo_router::services::transport;
+use apollo_router::Endpoint;
+use apollo_router::ListenAddr;
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::authorization::extract_token;
+use biscuit_router_plugin::config::IntrospectionConf;
+use biscuit_router_plugin::introspection::describe;
+use biscuit_router_plugin::introspection::TokenDescription;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use std::net::SocketAddr;
+
+use super::json_response;
+use super::read_body;
+use super::text_response;
+
+/// router endpoint describing tokens, like `biscuit inspect`, for operators
+///
+/// The caller authenticates with its own token, which must be signed by the root key and
+/// pass the admin policy.
+#[derive(Debug, Clone)]
+pub(crate) struct IntrospectionEndpoint {
+    listen: SocketAddr,
+    path: String,
+    root: biscuit::PublicKey,
+    admin_policy: String,
+}
+
+impl IntrospectionEndpoint {
+    pub(crate) fn new(
+        conf: &IntrospectionConf,
+        root: biscuit::PublicKey,
+    ) -> Result<Self, BoxError> {
+        // reject an invalid admin policy when loading the configuration instead of on every
+        // request
+        biscuit::Authorizer::new().add_code(&conf.admin_policy)?;
+
+        Ok(IntrospectionEndpoint {
+            listen: conf.listen,
+            path: conf.path.clone(),
+            root,
+            admin_policy: conf.admin_policy.clone(),
+        })
+    }
+
+    pub(crate) fn listen_addr(&self) -> ListenAddr {
+        ListenAddr::from(self.listen)
+    }
+
+    pub(crate) fn endpoint(&self) -> Endpoint {
+        let root = self.root;
+        let admin_policy = self.admin_policy.clone();
+        let service = tower::service_fn(move |request: transport::Request| {
+            let admin_policy = admin_policy.clone();
+            async move {
+                if request.method() != http::Method::POST {
+                    return text_response(
+                        http::StatusCode::METHOD_NOT_ALLOWED,
+                        "expected a POST request",
+                    );
+                }
+
+                match extract_token(&request, &root) {
+                    Ok(Some(admin)) => {
+                        if let Err(e) = authorize_admin(&admin, &admin_policy) {
+                            return text_response(http::StatusCode::FORBIDDEN, e.to_string());
+                        }
+                    }
+                    Ok(None) => {
+                        return text_response(http::StatusCode::UNAUTHORIZED, "missing token")
+                    }
+                    Err(e) => return text_response(http::StatusCode::UNAUTHORIZED, e.to_string()),
+                }
+
+                match introspect(request, &root).await {
+                    Ok(description) => json_response(http::StatusCode::OK, &description),
+                    Err(e) => text_response(http::StatusCode::BAD_REQUEST, e.to_string()),
+                }
+            }
+        });
+
+        Endpoint::new(self.path.clone(), service.boxed())
+    }
+}
+
+fn authorize_admin(token: &biscuit::Biscuit, admin_policy: &str) -> Result<(), BoxError> {
+    let mut authorizer = token.authorizer()?;
+    authorizer.add_code(admin_policy)?;
+    authorizer.set_time();
+    authorizer.authorize()?;
+    Ok(())
+}
+
+/// the request's body is the base64 encoded token to describe
+async fn introspect(
+    request: transport::Request,
+    root: &biscuit::PublicKey,
+) -> Result<TokenDescription, BoxError> {
+    let body = read_body(request.into_body()).await?;
+    let token = std::str::from_utf8(&body)?.trim();
+
+    describe(token, root)
+}
@@ -0,0 +1,63 @@
+use apollo_router::services::transport;
+use apollo_router::Endpoint;
+use apollo_router::ListenAddr;
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::config::IssuanceConf;
+use biscuit_router_plugin::issuance::Issuer;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use super::text_response;
+
+/// router endpoint exchanging upstream credentials for tokens
+#[derive(Debug, Clone)]
+pub(crate) struct IssuanceEndpoint {
+    listen: SocketAddr,
+    path: String,
+    issuer: Arc<Issuer>,
+}
+
+impl IssuanceEndpoint {
+    pub(crate) fn new(conf: &IssuanceConf, root: &biscuit::KeyPair) -> Result<Self, BoxError> {
+        Ok(IssuanceEndpoint {
+            listen: conf.listen,
+            path: conf.path.clone(),
+            issuer: Arc::new(Issuer::from_conf(conf, root)?),
+        })
+    }
+
+    pub(crate) fn listen_addr(&self) -> ListenAddr {
+        ListenAddr::from(self.listen)
+    }
+
+    pub(crate) fn endpoint(&self) -> Endpoint {
+        let issuer = self.issuer.clone();
+        let service = tower::service_fn(move |request: transport::Request| {
+            let issuer = issuer.clone();
+            async move {
+                if request.method() != http::Method::POST {
+                    return text_response(
+                        http::StatusCode::METHOD_NOT_ALLOWED,
+                        "expected a POST request",
+                    );
+                }
+
+                match issuer
+                    .issue(&request)
+                    .and_then(|token| token.to_base64().map_err(BoxError::from))
+                {
+                    Ok(token) => text_response(http::StatusCode::OK, token),
+                    Err(e) => {
+                        tracing::warn!("token issuance failed: {e}");
+                        text_response(http::StatusCode::UNAUTHORIZED, "invalid credentials")
+                    }
+                }
+            }
+        });
+
+        Endpoint::new(self.path.clone(), service.boxed())
+    }
+}
@@ -0,0 +1,182 @@
+use biscuit::builder::Fact;
+use biscuit::builder::Rule;
+use biscuit::builder::Term;
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::config::RateLimitConf;
+use biscuit_router_plugin::config::RateLimitKey;
+use lru::LruCache;
+use tower::BoxError;
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// rule extracting the limit set by the token, only the authority block and the authorizer
+/// are trusted by authorizer queries
+const LIMIT_RULE: &str = "limit($limit) <- rate_limit($limit)";
+
+/// fixed window rate limiter, counting requests per identity
+///
+/// Like the authorization cache, it belongs to the plugin instance, so the counters are
+/// reset when the router reloads its configuration. The windows are kept in a LRU of
+/// `capacity` identities: a client rotating through more identities than that resets the
+/// counters of the evicted ones.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    key: RateLimitKey,
+    requests: u64,
+    interval: Duration,
+    windows: Mutex<LruCache<String, Window>>,
+}
+
+/// who a request is counted against, and how many requests it is allowed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Identity {
+    key: String,
+    /// limit set by the token, overriding the configured one
+    limit: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    count: u64,
+}
+
+/// error returned when an identity made too many requests
+#[derive(Debug)]
+pub(crate) struct RateLimited;
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limit exceeded")
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+impl RateLimiter {
+    pub(crate) fn new(conf: &RateLimitConf) -> Result<Self, BoxError> {
+        // reject invalid rules when loading the configuration instead of on every request
+        if let RateLimitKey::Query(rule) = &conf.key {
+            Rule::try_from(rule.as_str())?;
+        }
+
+        Ok(RateLimiter {
+            key: conf.key.clone(),
+            requests: conf.requests,
+            interval: Duration::from_secs(conf.interval),
+            windows: Mutex::new(LruCache::new(conf.capacity)),
+        })
+    }
+
+    /// finds the identity of an authorized request
+    pub(crate) fn identify(
+        &self,
+        authorizer: &mut biscuit::Authorizer,
+        token: Option<&biscuit::Biscuit>,
+    ) -> Result<Option<Identity>, BoxError> {
+        let key = match &self.key {
+            RateLimitKey::RevocationId => token
+                .and_then(|token| token.revocation_identifiers().into_iter().next())
+                .map(hex::encode),
+            RateLimitKey::Query(rule) => {
+                let mut identities: Vec<String> = authorizer
+                    .query(rule.as_str())?
+                    .into_iter()
+                    .map(|fact: Fact| {
+                        let terms: Vec<String> = fact
+                            .predicate
+                            .terms
+                            .iter()
+                            .map(|term| term.to_string())
+                            .collect();
+                        terms.join(", ")
+                    })
+                    .collect();
+                // the order of the results is not guaranteed
+                identities.sort();
+                identities.into_iter().next()
+            }
+        };
+
+        let key = match key {
+            None => return Ok(None),
+            Some(key) => key,
+        };
+
+        let limit = authorizer
+            .query(LIMIT_RULE)?
+            .into_iter()
+            .filter_map(|fact: Fact| match fact.predicate.terms.first() {
+                Some(Term::Integer(limit)) => u64::try_from(*limit).ok(),
+                _ => None,
+            })
+            .min();
+
+        Ok(Some(Identity { key, limit }))
+    }
+
+    /// counts a request for this identity
+    pub(crate) fn check(&self, identity: &Identity) -> Result<(), RateLimited> {
+        let limit = identity.limit.unwrap_or(self.requests);
+        let now = Instant::now();
+
+        let mut windows = self.windows.lock().expect("rate limiter lock poisoned");
+        match windows.get_mut(&identity.key) {
+            Some(window) if now.duration_since(window.start) < self.interval => {
+                if window.count >= limit {
+                    return Err(RateLimited);
+                }
+                window.count += 1;
+            }
+            _ => {
+                if limit == 0 {
+                    return Err(RateLimited);
+                }
+                windows.put(
+                    identity.key.clone(),
+                    Window {
+                        start: now,
+                        count: 1,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(key: &str) -> Identity {
+        Identity {
+            key: key.to_string(),
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn eviction() {
+        let limiter = RateLimiter::new(&RateLimitConf {
+            key: RateLimitKey::RevocationId,
+            requests: 1,
+            interval: 60,
+            capacity: 2,
+        })
+        .unwrap();
+
+        assert!(limiter.check(&identity("a")).is_ok());
+        assert!(limiter.check(&identity("a")).is_err());
+        assert!(limiter.check(&identity("b")).is_ok());
+
+        // the window of `a` is the least recently used one, and its counter is lost
+        assert!(limiter.check(&identity("c")).is_ok());
+        assert!(limiter.check(&identity("c")).is_err());
+        assert!(limiter.check(&identity("a")).is_ok());
+    }
+}
@@ -0,0 +1,116 @@
+use apollo_router::graphql;
+use biscuit_router_plugin::authorization::Decision;
+use biscuit_router_plugin::authorization::Policies;
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// policies evaluated alongside the active ones, whose decisions are logged but never enforced
+///
+/// this is used to test new policies on real traffic before enforcing them
+#[derive(Debug, Clone)]
+pub(crate) struct Shadow {
+    policies: Policies,
+    summary: Arc<Summary>,
+}
+
+/// running count of the disagreements between the active and shadow policies
+#[derive(Debug, Default)]
+struct Summary {
+    requests: AtomicU64,
+    /// the active policies allowed the request, the shadow ones denied it
+    shadow_denied: AtomicU64,
+    /// the active policies denied the request, the shadow ones allowed it
+    shadow_allowed: AtomicU64,
+}
+
+impl Shadow {
+    pub(crate) fn new(policies: Policies) -> Self {
+        Shadow {
+            policies,
+            summary: Default::default(),
+        }
+    }
+
+    /// runs the shadow policies on the request, and compares with the active policies' result
+    pub(crate) fn evaluate(&self, request: &http::Request<graphql::Request>, active: &Decision) {
+        let shadow = match self.policies.authorize(request) {
+            Ok(authorization) => authorization.decision(),
+            Err(e) => Decision::Error(e.to_string()),
+        };
+
+        let requests = self.summary.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::info!(
+            monotonic_counter.biscuit.shadow.requests = 1u64,
+            allowed = shadow.is_allowed()
+        );
+
+        if active.is_allowed() == shadow.is_allowed() {
+            return;
+        }
+
+        let counter = if shadow.is_allowed() {
+            &self.summary.shadow_allowed
+        } else {
+            &self.summary.shadow_denied
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        tracing::info!(
+            monotonic_counter.biscuit.shadow.disagreements = 1u64,
+            allowed = shadow.is_allowed()
+        );
+
+        tracing::warn!(
+            active = ?active,
+            shadow = ?shadow,
+            "shadow policies disagreement: {} requests denied only by the shadow policies, {} requests allowed only by the shadow policies, out of {} requests",
+            self.summary.shadow_denied.load(Ordering::Relaxed),
+            self.summary.shadow_allowed.load(Ordering::Relaxed),
+            requests,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use biscuit_auth as biscuit;
+
+    fn request() -> http::Request<graphql::Request> {
+        let body: graphql::Request =
+            serde_json::from_value(serde_json::json!({ "query": "{ me { id } }" })).unwrap();
+        http::Request::builder().body(body).unwrap()
+    }
+
+    fn shadow_policies(code: &str) -> Shadow {
+        let root = biscuit::KeyPair::new();
+        Shadow::new(
+            Policies::builder(root.public(), code.to_string())
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn disagreements() {
+        let shadow = shadow_policies("allow if true;");
+        shadow.evaluate(&request(), &Decision::Allow(0));
+        shadow.evaluate(&request(), &Decision::Deny(0));
+        shadow.evaluate(&request(), &Decision::NoMatchingPolicy);
+
+        let summary = &shadow.summary;
+        assert_eq!(summary.requests.load(Ordering::Relaxed), 3);
+        assert_eq!(summary.shadow_allowed.load(Ordering::Relaxed), 2);
+        assert_eq!(summary.shadow_denied.load(Ordering::Relaxed), 0);
+
+        let shadow = shadow_policies("deny if true;");
+        shadow.evaluate(&request(), &Decision::Allow(0));
+        shadow.evaluate(&request(), &Decision::Deny(0));
+
+        let summary = &shadow.summary;
+        assert_eq!(summary.requests.load(Ordering::Relaxed), 2);
+        assert_eq!(summary.shadow_allowed.load(Ordering::Relaxed), 0);
+        assert_eq!(summary.shadow_denied.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,83 @@
+use apollo_router::services::transport;
+use apollo_router::Endpoint;
+use apollo_router::ListenAddr;
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::config::ThirdPartyConf;
+use biscuit_router_plugin::third_party::append_block;
+use biscuit_router_plugin::third_party::ThirdPartyRequest;
+use biscuit_router_plugin::third_party::ThirdPartyResponse;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use std::sync::Arc;
+
+use super::json_response;
+use super::read_body;
+use super::text_response;
+
+/// router endpoint requesting third party blocks from external authorities, for clients
+/// that cannot run Biscuit
+#[derive(Debug, Clone)]
+pub(crate) struct ThirdPartyEndpoint {
+    conf: Arc<ThirdPartyConf>,
+    root: biscuit::PublicKey,
+}
+
+impl ThirdPartyEndpoint {
+    pub(crate) fn new(conf: &ThirdPartyConf, root: biscuit::PublicKey) -> Result<Self, BoxError> {
+        // reject invalid keys when loading the configuration
+        for authority in conf.authorities.values() {
+            authority.public_key()?;
+        }
+
+        Ok(ThirdPartyEndpoint {
+            conf: Arc::new(conf.clone()),
+            root,
+        })
+    }
+
+    pub(crate) fn listen_addr(&self) -> ListenAddr {
+        ListenAddr::from(self.conf.listen)
+    }
+
+    pub(crate) fn endpoint(&self) -> Endpoint {
+        let this = self.clone();
+        let service = tower::service_fn(move |request: transport::Request| {
+            let this = this.clone();
+            async move {
+                if request.method() != http::Method::POST {
+                    return text_response(
+                        http::StatusCode::METHOD_NOT_ALLOWED,
+                        "expected a POST request",
+                    );
+                }
+
+                match this.append(request).await {
+                    Ok(response) => json_response(http::StatusCode::OK, &response),
+                    Err(e) => text_response(http::StatusCode::BAD_REQUEST, e.to_string()),
+                }
+            }
+        });
+
+        Endpoint::new(self.conf.path.clone(), service.boxed())
+    }
+
+    async fn append(&self, request: transport::Request) -> Result<ThirdPartyResponse, BoxError> {
+        let body = read_body(request.into_body()).await?;
+        let request: ThirdPartyRequest = serde_json::from_slice(&body)?;
+
+        let authority = self
+            .conf
+            .authorities
+            .get(&request.authority)
+            .ok_or_else(|| BoxError::from(format!("unknown authority `{}`", request.authority)))?;
+
+        // only tokens the router would accept are sent to the authorities
+        let token = biscuit::Biscuit::from_base64(&request.token, &self.root)?;
+        let token = append_block(&token, authority).await?;
+
+        Ok(ThirdPartyResponse {
+            token: token.to_base64()?,
+        })
+    }
+}
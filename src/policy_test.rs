@@ -0,0 +1,173 @@
+use apollo_router::graphql;
+use biscuit_auth as biscuit;
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::authorization::Decision;
+use crate::authorization::Policies;
+use crate::BoxError;
+
+/// a file of policy test cases, in YAML
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestFile {
+    pub tests: Vec<TestCase>,
+}
+
+impl TestFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+}
+
+/// a request to authorize, and the expected result
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    /// Datalog code of the token's authority block. If absent, the request is unauthenticated
+    pub authority: Option<String>,
+    /// Datalog code of the attenuation blocks appended to the token
+    #[serde(default)]
+    pub blocks: Vec<String>,
+    /// seals the token after the attenuation blocks
+    #[serde(default)]
+    pub sealed: bool,
+    pub operation: String,
+    pub operation_name: Option<String>,
+    /// HTTP headers of the request, for the request facts
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub expected: Expected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Expected {
+    Allow,
+    Deny,
+}
+
+impl TestCase {
+    /// runs the plugin's authorization path on the test request
+    ///
+    /// the token is signed by `root`, that the policies must use instead of the configured
+    /// root key, since only the token's content matters here. Requests the plugin would reject
+    /// before running the authorizer, like tokens over the limits, give a `Decision::Error`
+    pub fn run(&self, policies: &Policies, root: &biscuit::KeyPair) -> Result<Decision, BoxError> {
+        let body: graphql::Request = serde_json::from_value(serde_json::json!({
+            "query": self.operation,
+            "operationName": self.operation_name,
+        }))?;
+
+        let mut request = http::Request::builder().method("POST");
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(token) = self.token(root)? {
+            request = request.header("Authorization", format!("Bearer {}", token.to_base64()?));
+        }
+        let request = request.body(body)?;
+
+        Ok(match policies.authorize(&request) {
+            Ok(authorization) => authorization.decision(),
+            Err(e) => Decision::Error(e.to_string()),
+        })
+    }
+
+    pub fn passed(&self, decision: &Decision) -> bool {
+        decision.is_allowed() == (self.expected == Expected::Allow)
+    }
+
+    fn token(&self, root: &biscuit::KeyPair) -> Result<Option<biscuit::Biscuit>, BoxError> {
+        let authority = match self.authority.as_ref() {
+            None => return Ok(None),
+            Some(authority) => authority,
+        };
+
+        let mut builder = biscuit::Biscuit::builder();
+        builder.add_code(authority)?;
+        let mut token = builder.build(root)?;
+
+        for code in &self.blocks {
+            let mut block = biscuit::builder::BlockBuilder::new();
+            block.add_code(code)?;
+            token = token.append(block)?;
+        }
+        if self.sealed {
+            token = token.seal()?;
+        }
+
+        Ok(Some(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authorization::SealedToken;
+    use crate::config::LimitsConf;
+    use crate::config::OperationKind;
+    use crate::config::SealedConf;
+
+    #[test]
+    fn authorizer_policies() {
+        let code = std::fs::read_to_string("authorizer.datalog").unwrap();
+        let root = biscuit::KeyPair::new();
+        let policies = Policies::builder(root.public(), code).build().unwrap();
+
+        for test in TestFile::load("authorizer.test.yaml").unwrap().tests {
+            let decision = test.run(&policies, &root).unwrap();
+            assert!(test.passed(&decision), "{}: {:?}", test.name, decision);
+        }
+    }
+
+    #[test]
+    fn plugin_options() {
+        let root = biscuit::KeyPair::new();
+        let policies = Policies::builder(root.public(), "allow if true;".to_string())
+            .sealed(SealedConf {
+                require: vec![OperationKind::Mutation],
+                reject: false,
+            })
+            .limits(LimitsConf {
+                max_blocks: Some(1),
+                ..LimitsConf::default()
+            })
+            .build()
+            .unwrap();
+        let test = |yaml: &str| {
+            let test: TestCase = serde_yaml::from_str(yaml).unwrap();
+            test.run(&policies, &root).unwrap()
+        };
+
+        let decision = test(
+            r#"
+            name: unsealed
+            authority: "user(1);"
+            operation: "mutation { m }"
+            expected: deny"#,
+        );
+        assert_eq!(decision, Decision::SealedToken(SealedToken::Required));
+        let decision = test(
+            r#"
+            name: sealed
+            authority: "user(1);"
+            sealed: true
+            operation: "mutation { m }"
+            expected: allow"#,
+        );
+        assert_eq!(decision, Decision::Allow(0));
+        // too many blocks, the plugin rejects the request before running the authorizer
+        let decision = test(
+            r#"
+            name: blocks
+            authority: "user(1);"
+            blocks: ["check if true;"]
+            operation: "{ q }"
+            expected: deny"#,
+        );
+        assert!(matches!(decision, Decision::Error(_)));
+    }
+}
@@ -0,0 +1,273 @@
+//! facts derived from the HTTP request, as configured in `request_facts`
+use apollo_router::graphql;
+use biscuit::builder::Fact;
+use biscuit::macros::fact;
+use biscuit_auth as biscuit;
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+use crate::config::RequestFactsConf;
+use crate::BoxError;
+
+const CLIENT_NAME_HEADER: &str = "apollographql-client-name";
+const CLIENT_VERSION_HEADER: &str = "apollographql-client-version";
+
+/// generates the configured facts for a request
+pub fn facts(
+    conf: &RequestFactsConf,
+    request: &http::Request<graphql::Request>,
+) -> Result<Vec<Fact>, BoxError> {
+    let mut facts = Vec::new();
+
+    if conf.client {
+        if let Some(name) = header(request, CLIENT_NAME_HEADER) {
+            facts.push(fact!("client_name({name})", name = name));
+        }
+        if let Some(version) = header(request, CLIENT_VERSION_HEADER) {
+            facts.push(fact!("client_version({version})", version = version));
+        }
+    }
+
+    if conf.method {
+        facts.push(fact!(
+            "method({method})",
+            method = request.method().as_str()
+        ));
+    }
+
+    if let Some(ip) = conf
+        .ip_header
+        .as_deref()
+        .and_then(|name| client_ip(request, name))
+    {
+        facts.push(fact!("ip({ip})", ip = ip.to_string()));
+        match ip {
+            IpAddr::V4(ip) => facts.push(fact!("ipv4({ip})", ip = u32::from(ip) as i64)),
+            IpAddr::V6(ip) => {
+                let [a, b, c, d] = ipv6_words(u128::from(ip));
+                facts.push(fact!(
+                    "ipv6({a}, {b}, {c}, {d})",
+                    a = a,
+                    b = b,
+                    c = c,
+                    d = d
+                ));
+            }
+        }
+    }
+
+    for name in &conf.headers {
+        let name = name.to_lowercase();
+        for value in request.headers().get_all(name.as_str()) {
+            // values that are not valid strings cannot be checked by a token
+            if let Ok(value) = value.to_str() {
+                facts.push(fact!(
+                    "header({name}, {value})",
+                    name = name.as_str(),
+                    value = value
+                ));
+            }
+        }
+    }
+
+    Ok(facts)
+}
+
+/// Datalog check restricting a token to clients in one of these networks, in CIDR notation
+/// like `10.0.0.0/8` or `2001:db8::/32`
+///
+/// It relies on the `ipv4` and `ipv6` facts, so the router must be configured with an
+/// `ip_header`.
+pub fn network_check(networks: &[&str]) -> Result<String, BoxError> {
+    if networks.is_empty() {
+        return Err(BoxError::from("no network to check"));
+    }
+
+    let ranges = networks
+        .iter()
+        .map(|network| network_rule(network))
+        .collect::<Result<Vec<_>, BoxError>>()?;
+
+    Ok(format!("check if {}", ranges.join(" or ")))
+}
+
+fn network_rule(network: &str) -> Result<String, BoxError> {
+    if network.contains(':') {
+        let (first, last) = ipv6_network(network)?;
+        let (first, last) = (ipv6_words(first), ipv6_words(last));
+
+        // the words covered by the prefix are fixed, then the first word that differs is a
+        // range, and the next ones can take any value
+        let mut conditions = Vec::new();
+        for (index, (first, last)) in first.iter().zip(last.iter()).enumerate() {
+            if first == last {
+                conditions.push(format!("$w{index} == {first}"));
+            } else {
+                conditions.push(format!("$w{index} >= {first}, $w{index} <= {last}"));
+                break;
+            }
+        }
+        Ok(format!(
+            "ipv6($w0, $w1, $w2, $w3), {}",
+            conditions.join(", ")
+        ))
+    } else {
+        let (first, last) = ipv4_network(network)?;
+        Ok(format!("ipv4($ip), $ip >= {first}, $ip <= {last}"))
+    }
+}
+
+/// first and last addresses of an IPv4 network in CIDR notation
+pub fn ipv4_network(network: &str) -> Result<(u32, u32), BoxError> {
+    let (address, prefix) = split_network(network, 32)?;
+    let address: Ipv4Addr = address.parse()?;
+
+    let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+    let first = u32::from(address) & mask;
+    Ok((first, first | !mask))
+}
+
+/// first and last addresses of an IPv6 network in CIDR notation
+pub fn ipv6_network(network: &str) -> Result<(u128, u128), BoxError> {
+    let (address, prefix) = split_network(network, 128)?;
+    let address: Ipv6Addr = address.parse()?;
+
+    let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+    let first = u128::from(address) & mask;
+    Ok((first, first | !mask))
+}
+
+fn split_network(network: &str, max_prefix: u32) -> Result<(&str, u32), BoxError> {
+    let (address, prefix) = network
+        .split_once('/')
+        .ok_or_else(|| BoxError::from(format!("invalid network `{network}`")))?;
+    let prefix: u32 = prefix.parse()?;
+    if prefix > max_prefix {
+        return Err(BoxError::from(format!("invalid network `{network}`")));
+    }
+    Ok((address, prefix))
+}
+
+/// Datalog integers are 64 bits, so IPv6 addresses are split in four 32 bits words
+fn ipv6_words(address: u128) -> [i64; 4] {
+    [
+        (address >> 96) as u32 as i64,
+        (address >> 64) as u32 as i64,
+        (address >> 32) as u32 as i64,
+        address as u32 as i64,
+    ]
+}
+
+fn header<'a>(request: &'a http::Request<graphql::Request>, name: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn client_ip(request: &http::Request<graphql::Request>, header_name: &str) -> Option<IpAddr> {
+    header(request, header_name)?
+        .rsplit(',')
+        .next()
+        .and_then(|ip| ip.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn networks() {
+        assert_eq!(
+            ipv4_network("10.1.2.3/8").unwrap(),
+            (0x0a000000, 0x0affffff)
+        );
+        assert_eq!(
+            ipv4_network("192.168.1.1/32").unwrap(),
+            (0xc0a80101, 0xc0a80101)
+        );
+        assert_eq!(ipv4_network("0.0.0.0/0").unwrap(), (0, u32::MAX));
+        assert!(ipv4_network("10.0.0.0/33").is_err());
+        assert!(ipv4_network("10.0.0.0").is_err());
+
+        assert_eq!(
+            ipv6_network("2001:db8::1/32").unwrap(),
+            (
+                0x2001_0db8_0000_0000_0000_0000_0000_0000,
+                0x2001_0db8_ffff_ffff_ffff_ffff_ffff_ffff
+            )
+        );
+        assert_eq!(ipv6_network("::/0").unwrap(), (0, u128::MAX));
+        assert!(ipv6_network("2001:db8::/129").is_err());
+
+        assert_eq!(
+            network_check(&["2001:db8::/48"]).unwrap(),
+            "check if ipv6($w0, $w1, $w2, $w3), $w0 == 536939960, $w1 >= 0, $w1 <= 65535"
+        );
+        assert_eq!(
+            network_check(&["10.0.0.0/8", "192.168.0.0/16"]).unwrap(),
+            "check if ipv4($ip), $ip >= 167772160, $ip <= 184549375 \
+             or ipv4($ip), $ip >= 3232235520, $ip <= 3232301055"
+        );
+    }
+
+    #[test]
+    fn request_facts() {
+        let conf = RequestFactsConf {
+            client: true,
+            method: true,
+            ip_header: Some("x-forwarded-for".to_string()),
+            headers: vec!["X-Tenant".to_string()],
+        };
+
+        let request = http::Request::builder()
+            .method("GET")
+            .header(CLIENT_NAME_HEADER, "web")
+            .header(CLIENT_VERSION_HEADER, "1.0")
+            .header("x-forwarded-for", "1.1.1.1, 10.0.0.1")
+            .header("x-tenant", "acme")
+            .body(serde_json::from_value::<graphql::Request>(serde_json::json!({})).unwrap())
+            .unwrap();
+
+        let facts: Vec<String> = facts(&conf, &request)
+            .unwrap()
+            .iter()
+            .map(|fact| fact.to_string())
+            .collect();
+        assert_eq!(
+            facts,
+            vec![
+                "client_name(\"web\")",
+                "client_version(\"1.0\")",
+                "method(\"GET\")",
+                "ip(\"10.0.0.1\")",
+                "ipv4(167772161)",
+                "header(\"x-tenant\", \"acme\")",
+            ]
+        );
+
+        let request = http::Request::builder()
+            .header("x-forwarded-for", "2001:db8::1")
+            .body(serde_json::from_value::<graphql::Request>(serde_json::json!({})).unwrap())
+            .unwrap();
+        let facts: Vec<String> = super::facts(&conf, &request)
+            .unwrap()
+            .iter()
+            .map(|fact| fact.to_string())
+            .collect();
+        assert_eq!(
+            facts,
+            vec![
+                "method(\"GET\")",
+                "ip(\"2001:db8::1\")",
+                "ipv6(536939960, 0, 0, 1)",
+            ]
+        );
+
+        assert!(super::facts(&RequestFactsConf::default(), &request)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+//! third party blocks requested from external authorities, on behalf of clients
+//!
+//! An authority's endpoint receives a `POST` request authenticated by the client's token in
+//! the `Authorization` header, with the base64 encoded third party block request as body.
+//! It answers with the base64 encoded third party block, after checking that the request was
+//! generated from that token. The `orga` and `user` subgraphs implement that side.
+use biscuit_auth as biscuit;
+use hyper::body::HttpBody;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::config::AuthorityConf;
+use crate::BoxError;
+
+/// third party blocks are small, larger responses are refused
+const MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+/// body of a request to the router's third party endpoint
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThirdPartyRequest {
+    /// base64 encoded token
+    pub token: String,
+    /// name of the authority in the configuration
+    pub authority: String,
+}
+
+/// body of a response of the router's third party endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct ThirdPartyResponse {
+    /// base64 encoded token, with the third party block appended
+    pub token: String,
+}
+
+/// requests a third party block from the authority, and appends it to the token
+pub async fn append_block(
+    token: &biscuit::Biscuit,
+    authority: &AuthorityConf,
+) -> Result<biscuit::Biscuit, BoxError> {
+    let public_key = authority.public_key()?;
+    let request = token.third_party_request()?.serialize_base64()?;
+
+    let request = http::Request::builder()
+        .method(http::Method::POST)
+        .uri(&authority.url)
+        .header(
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", token.to_base64()?),
+        )
+        .body(hyper::Body::from(request))?;
+    let (status, body) = tokio::time::timeout(authority.timeout(), async {
+        let response = hyper::Client::new().request(request).await?;
+        let status = response.status();
+
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            if bytes.len() + chunk.len() > MAX_BLOCK_SIZE {
+                return Err(BoxError::from("the authority's response is too large"));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok::<_, BoxError>((status, bytes))
+    })
+    .await
+    .map_err(|_| BoxError::from("the authority did not answer in time"))??;
+    let body = std::str::from_utf8(&body)?.trim();
+    if !status.is_success() {
+        return Err(BoxError::from(format!(
+            "the authority refused the third party block request: {body}"
+        )));
+    }
+
+    // the block must be signed by the authority's key
+    let block = biscuit::ThirdPartyBlock::deserialize_base64(body)?;
+    Ok(token.append_third_party(public_key, block)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use biscuit::macros::biscuit;
+    use biscuit::macros::block;
+    use hyper::service::make_service_fn;
+    use hyper::service::service_fn;
+
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    /// authority signing every block request with `key`, and answering on a local port
+    fn authority(key: biscuit::KeyPair) -> SocketAddr {
+        let key = std::sync::Arc::new(key);
+        let make_service = make_service_fn(move |_| {
+            let key = key.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: http::Request<hyper::Body>| {
+                    let key = key.clone();
+                    async move {
+                        let body = hyper::body::to_bytes(request.into_body()).await?;
+                        let request = biscuit::ThirdPartyRequest::deserialize_base64(
+                            std::str::from_utf8(&body)?.trim(),
+                        )?;
+                        let block = request
+                            .create_block(&key.private(), block!("orga_service_admin(true);"))?
+                            .serialize_base64()?;
+                        Ok::<_, BoxError>(http::Response::new(hyper::Body::from(block)))
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    fn conf(addr: SocketAddr, public_key: biscuit::PublicKey) -> AuthorityConf {
+        AuthorityConf {
+            url: format!("http://{addr}/third-party-block"),
+            public_key: public_key.to_bytes_hex(),
+            timeout_ms: Some(100),
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let root = biscuit::KeyPair::new();
+        let orga = biscuit::KeyPair::new();
+        let token = biscuit!("user(1);").build(&root).unwrap();
+
+        let addr = authority(biscuit::KeyPair::from(&orga.private()));
+        let token = append_block(&token, &conf(addr, orga.public()))
+            .await
+            .unwrap();
+
+        assert_eq!(token.block_count(), 2);
+        assert_eq!(
+            token.external_public_keys(),
+            vec![None, Some(orga.public())]
+        );
+    }
+
+    #[tokio::test]
+    async fn external_key() {
+        let root = biscuit::KeyPair::new();
+        let token = biscuit!("user(1);").build(&root).unwrap();
+
+        // the authority signs with another key than the configured one
+        let addr = authority(biscuit::KeyPair::new());
+        assert!(
+            append_block(&token, &conf(addr, biscuit::KeyPair::new().public()))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let root = biscuit::KeyPair::new();
+        let token = biscuit!("user(1);").build(&root).unwrap();
+
+        // accepts connections, but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+
+        let e = append_block(&token, &conf(addr, biscuit::KeyPair::new().public()))
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "the authority did not answer in time");
+    }
+}
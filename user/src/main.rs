@@ -2,8 +2,9 @@ use apollo_compiler::values::OperationType;
 use async_graphql::{Context, Object, Result, ID};
 use async_graphql::{EmptyMutation, EmptySubscription, Schema};
 use biscuit_auth as biscuit;
+use hyper::body::HttpBody;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server};
+use hyper::{Body, Request, Response, Server, StatusCode};
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::convert::Infallible;
@@ -13,6 +14,10 @@ use std::net::SocketAddr;
 type BoxError = Box<dyn Error + Send + Sync>;
 
 async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.uri().path() == "/third-party-block" {
+        return Ok(third_party_block(req).await);
+    }
+
     let root = biscuit::PublicKey::from_bytes_hex(&std::env::var("ROOT_KEY").unwrap()).unwrap();
 
     let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
@@ -121,6 +126,76 @@ fn validate_request(
     Ok(res.get(0).map(|(id,)| UserId(ID(id.to_string()))))
 }
 
+/// answers third party block requests with a block signed by this subgraph's key
+///
+/// The request is authenticated by the token it was generated from, and the block is only
+/// given to the user admins.
+async fn third_party_block(req: Request<Body>) -> Response<Body> {
+    match create_third_party_block(req).await {
+        Ok(block) => Response::new(Body::from(block)),
+        Err(e) => {
+            println!("third party block error: {:?}", e);
+            Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from(e.to_string()))
+                .unwrap()
+        }
+    }
+}
+
+/// third party block requests only contain public keys, larger bodies are refused
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+async fn create_third_party_block(req: Request<Body>) -> Result<String, BoxError> {
+    let root = biscuit::PublicKey::from_bytes_hex(&std::env::var("ROOT_KEY")?)?;
+    let private_key = biscuit::PrivateKey::from_bytes_hex(&std::env::var("THIRD_PARTY_KEY")?)?;
+
+    let token = match extract_token(&req, &root)? {
+        None => return Err(Box::<dyn Error + Send + Sync>::from("missing token")),
+        Some(token) => token,
+    };
+
+    let mut body = req.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(Box::<dyn Error + Send + Sync>::from(
+                "request body too large",
+            ));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let request = std::str::from_utf8(&bytes)?.trim();
+
+    // the block can only be appended to the token that authenticated the request
+    if request != token.third_party_request()?.serialize_base64()? {
+        return Err(Box::<dyn Error + Send + Sync>::from(
+            "the request was not generated from this token",
+        ));
+    }
+
+    let mut authorizer = biscuit::Authorizer::new();
+    authorizer.add_code(
+        r#"
+        user_admin(1);
+
+        allow if user($id), user_admin($id);
+        deny if true;"#,
+    )?;
+    authorizer.set_time();
+    authorizer.add_token(&token)?;
+    authorizer.authorize()?;
+
+    let mut block = biscuit::builder::BlockBuilder::new();
+    block.add_code("user_service_admin(true);")?;
+
+    let request = biscuit::ThirdPartyRequest::deserialize_base64(request)?;
+    Ok(request
+        .create_block(&private_key, block)?
+        .serialize_base64()?)
+}
+
 static USERS: Lazy<HashMap<&str, User>> = Lazy::new(|| {
     println!("initializing");
     let mut m = HashMap::new();
@@ -0,0 +1,42 @@
+mod coverage;
+mod explain;
+mod mint;
+mod test;
+
+use anyhow::Result;
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::BoxError;
+use clap::Subcommand;
+
+use std::path::Path;
+
+#[derive(Subcommand, Debug)]
+pub enum BiscuitAction {
+    /// Print which policy decides each root field of the supergraph
+    Coverage(coverage::Coverage),
+    /// Explain how the router authorizes a request
+    Explain(explain::Explain),
+    /// Mint a token signed by the router's root key
+    Mint(mint::Mint),
+    /// Run the policy test cases described in YAML files
+    Test(test::Test),
+}
+
+impl BiscuitAction {
+    pub fn execute(&self) -> Result<()> {
+        let res = match self {
+            BiscuitAction::Coverage(coverage) => coverage.execute(),
+            BiscuitAction::Explain(explain) => explain.execute(),
+            BiscuitAction::Mint(mint) => mint.execute(),
+            BiscuitAction::Test(test) => test.execute(),
+        };
+
+        res.map_err(|e| anyhow::anyhow!(e))
+    }
+}
+
+/// reads a base64 encoded token, as created by the `biscuit` CLI
+fn read_token(path: &Path, root: &biscuit::PublicKey) -> Result<biscuit::Biscuit, BoxError> {
+    let data = std::fs::read_to_string(path)?;
+    Ok(biscuit::Biscuit::from_base64(data.trim(), root)?)
+}
@@ -0,0 +1,121 @@
+use apollo_compiler::values::OperationType;
+use apollo_router::graphql;
+use biscuit_router_plugin::authorization::Policies;
+use biscuit_router_plugin::config::Conf;
+use biscuit_router_plugin::BoxError;
+use clap::Args;
+
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct Coverage {
+    /// Router configuration file, containing the public root key and the authorizer code
+    #[clap(long, default_value = "router.yaml")]
+    config: PathBuf,
+    /// Supergraph schema
+    #[clap(long, default_value = "supergraph.graphql")]
+    schema: PathBuf,
+    /// Sample token used to simulate authenticated requests, can be repeated
+    #[clap(long = "token")]
+    tokens: Vec<PathBuf>,
+}
+
+impl Coverage {
+    pub fn execute(&self) -> Result<(), BoxError> {
+        let policies = Policies::from_conf(&Conf::from_router_config(&self.config)?)?;
+
+        let mut tokens = Vec::new();
+        for path in &self.tokens {
+            let token = super::read_token(path, &policies.root())?;
+            tokens.push((path.display().to_string(), token.to_base64()?));
+        }
+
+        let schema = std::fs::read_to_string(&self.schema)?;
+        let mut anonymous = Vec::new();
+
+        for (operation_type, field) in root_fields(&schema) {
+            let (keyword, type_name) = match operation_type {
+                OperationType::Mutation => ("mutation", "Mutation"),
+                _ => ("query", "Query"),
+            };
+            println!("{type_name}.{field}");
+
+            /*** Simulate a request selecting only this root field ***/
+            let query = format!("{keyword} {{ {field} }}");
+
+            let (allowed, decision) = authorize(&policies, &query, None)?;
+            println!("    anonymous: {decision}");
+            if allowed {
+                anonymous.push(format!("{type_name}.{field}"));
+            }
+
+            for (name, token) in &tokens {
+                let (_, decision) = authorize(&policies, &query, Some(token))?;
+                println!("    {name}: {decision}");
+            }
+        }
+
+        println!();
+        if anonymous.is_empty() {
+            println!("no root field is reachable anonymously");
+        } else {
+            println!(
+                "root fields reachable anonymously: {}",
+                anonymous.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// runs the plugin's authorization path on a request, and describes the decision
+fn authorize(
+    policies: &Policies,
+    query: &str,
+    token: Option<&str>,
+) -> Result<(bool, String), BoxError> {
+    let body: graphql::Request = serde_json::from_value(serde_json::json!({ "query": query }))?;
+
+    let mut request = http::Request::builder().method("POST");
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {token}"));
+    }
+    let request = request.body(body)?;
+
+    Ok(match policies.authorize(&request) {
+        Ok(authorization) => {
+            let decision = authorization.decision();
+            (
+                decision.is_allowed(),
+                decision.describe(&authorization.authorizer),
+            )
+        }
+        // the plugin rejects the request before running the authorizer
+        Err(e) => (false, format!("rejected: {e}")),
+    })
+}
+
+/// lists the fields of the `Query` and `Mutation` root types
+fn root_fields(schema: &str) -> Vec<(OperationType, String)> {
+    let compiler = apollo_compiler::ApolloCompiler::new(schema);
+
+    let mut fields = Vec::new();
+    for (operation_type, type_name) in [
+        (OperationType::Query, "Query"),
+        (OperationType::Mutation, "Mutation"),
+    ] {
+        for object in compiler.db.object_types().iter() {
+            if object.name() == type_name {
+                fields.extend(
+                    object
+                        .fields_definition()
+                        .iter()
+                        .map(|field| (operation_type, field.name().to_string())),
+                );
+            }
+        }
+    }
+
+    fields
+}
@@ -0,0 +1,62 @@
+use apollo_router::graphql;
+use biscuit_router_plugin::authorization::Policies;
+use biscuit_router_plugin::config::Conf;
+use biscuit_router_plugin::BoxError;
+use clap::Args;
+
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct Explain {
+    /// Router configuration file
+    #[clap(long, default_value = "router.yaml")]
+    config: PathBuf,
+    /// Token sent in the Authorization header. If absent, the request is unauthenticated
+    #[clap(long)]
+    token: Option<PathBuf>,
+    /// GraphQL operation
+    #[clap(long)]
+    query: String,
+    #[clap(long)]
+    operation_name: Option<String>,
+    /// JSON object of the operation's variables
+    #[clap(long)]
+    variables: Option<String>,
+    /// HTTP header sent with the request, as `name: value`, can be repeated
+    #[clap(long = "header")]
+    headers: Vec<String>,
+}
+
+impl Explain {
+    pub fn execute(&self) -> Result<(), BoxError> {
+        let policies = Policies::from_conf(&Conf::from_router_config(&self.config)?)?;
+
+        let variables: serde_json::Value = match self.variables.as_deref() {
+            None => serde_json::json!({}),
+            Some(variables) => serde_json::from_str(variables)?,
+        };
+        let body: graphql::Request = serde_json::from_value(serde_json::json!({
+            "query": self.query,
+            "operationName": self.operation_name,
+            "variables": variables,
+        }))?;
+
+        let mut request = http::Request::builder().method("POST");
+        for header in &self.headers {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| BoxError::from(format!("invalid header `{header}`")))?;
+            request = request.header(name.trim(), value.trim());
+        }
+        if let Some(path) = self.token.as_ref() {
+            let token = std::fs::read_to_string(path)?;
+            request = request.header("Authorization", format!("Bearer {}", token.trim()));
+        }
+        let request = request.body(body)?;
+
+        let authorization = policies.authorize(&request)?;
+        println!("{}", policies.explain(&authorization));
+
+        Ok(())
+    }
+}
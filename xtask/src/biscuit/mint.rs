@@ -0,0 +1,77 @@
+use biscuit::macros::check;
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::config::Conf;
+use biscuit_router_plugin::issuance::expiration;
+use biscuit_router_plugin::request::network_check;
+use biscuit_router_plugin::BoxError;
+use clap::Args;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Args, Debug)]
+pub struct Mint {
+    /// Router configuration file, containing the public root key
+    #[clap(long, default_value = "router.yaml")]
+    config: PathBuf,
+    /// File containing the hex encoded root private key
+    #[clap(long)]
+    private_key_file: PathBuf,
+    /// Fact added to the authority block, can be repeated
+    #[clap(long = "fact")]
+    facts: Vec<String>,
+    /// Validity of the token in seconds, enforced by a time check in the authority block
+    #[clap(long)]
+    ttl: Option<u64>,
+    /// IPv4 or IPv6 network in CIDR notation the client must be in, enforced by a check in the
+    /// authority block. Can be repeated to allow multiple networks
+    #[clap(long = "network")]
+    networks: Vec<String>,
+    /// Datalog code of an attenuation block appended to the token, can be repeated
+    #[clap(long = "block")]
+    blocks: Vec<String>,
+}
+
+impl Mint {
+    pub fn execute(&self) -> Result<(), BoxError> {
+        let conf = Conf::from_router_config(&self.config)?;
+
+        let private_key = std::fs::read_to_string(&self.private_key_file)?;
+        let root =
+            biscuit::KeyPair::from(&biscuit::PrivateKey::from_bytes_hex(private_key.trim())?);
+
+        /*** The router would reject tokens signed by another key ***/
+        if root.public() != conf.public_root()? {
+            return Err(BoxError::from(format!(
+                "the private key does not match the public root key configured in {}",
+                self.config.display()
+            )));
+        }
+
+        let mut builder = biscuit::Biscuit::builder();
+        for fact in &self.facts {
+            builder.add_fact(fact.as_str())?;
+        }
+        if let Some(ttl) = self.ttl {
+            let expiration = expiration(Duration::from_secs(ttl))?;
+            builder.add_check(check!(
+                "check if time($time), $time < {expiration}",
+                expiration = expiration
+            ))?;
+        }
+        if !self.networks.is_empty() {
+            let networks: Vec<&str> = self.networks.iter().map(String::as_str).collect();
+            builder.add_check(network_check(&networks)?.as_str())?;
+        }
+        let mut token = builder.build(&root)?;
+
+        for code in &self.blocks {
+            let mut block = biscuit::builder::BlockBuilder::new();
+            block.add_code(code)?;
+            token = token.append(block)?;
+        }
+
+        println!("{}", token.to_base64()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use biscuit_auth as biscuit;
+use biscuit_router_plugin::authorization::PoliciesBuilder;
+use biscuit_router_plugin::config::Conf;
+use biscuit_router_plugin::policy_test::TestFile;
+use biscuit_router_plugin::BoxError;
+use clap::Args;
+
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct Test {
+    /// Router configuration file, containing the authorizer code
+    #[clap(long, default_value = "router.yaml")]
+    config: PathBuf,
+    /// YAML files containing the test cases
+    #[clap(default_value = "authorizer.test.yaml")]
+    files: Vec<PathBuf>,
+}
+
+impl Test {
+    pub fn execute(&self) -> Result<(), BoxError> {
+        // the sample tokens are signed by a test key instead of the root key
+        let root = biscuit::KeyPair::new();
+        let policies = PoliciesBuilder::from_conf(&Conf::from_router_config(&self.config)?)?
+            .root(root.public())
+            .build()?;
+
+        let mut failures = 0;
+        for path in &self.files {
+            println!("{}", path.display());
+
+            for test in TestFile::load(path)?.tests {
+                match test.run(&policies, &root) {
+                    Ok(decision) if test.passed(&decision) => {
+                        println!("    ok: {}", test.name);
+                    }
+                    Ok(decision) => {
+                        failures += 1;
+                        println!(
+                            "    FAILED: {} (expected {:?}, got {:?})",
+                            test.name, test.expected, decision
+                        );
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        println!("    ERROR: {}: {}", test.name, e);
+                    }
+                }
+            }
+        }
+
+        if failures > 0 {
+            return Err(BoxError::from(format!("{failures} policy tests failed")));
+        }
+        Ok(())
+    }
+}
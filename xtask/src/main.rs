@@ -1,5 +1,8 @@
+mod biscuit;
+
 use anyhow::Result;
 use apollo_router_scaffold::RouterAction;
+use biscuit::BiscuitAction;
 use clap::Parser;
 use clap::Subcommand;
 
@@ -22,12 +25,18 @@ enum Action {
         #[clap(subcommand)]
         action: RouterAction,
     },
+    /// Biscuit authorization tooling
+    Biscuit {
+        #[clap(subcommand)]
+        action: BiscuitAction,
+    },
 }
 
 impl Action {
     fn execute(&self) -> Result<()> {
         match self {
             Action::Router { action } => action.execute(),
+            Action::Biscuit { action } => action.execute(),
         }
     }
 }